@@ -0,0 +1,341 @@
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    color::Color, light::Light, material::Coloration, point::Point, scene::Scene, vector::Transform,
+};
+
+/// Values that keyframes can animate.
+pub trait Animatable: Copy {
+    /// `self` moved by `amount` times the difference from `from` to `to`.
+    fn offset(&self, from: &Self, to: &Self, amount: f64) -> Self;
+
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        self.offset(self, other, t)
+    }
+}
+
+impl Animatable for f32 {
+    fn offset(&self, from: &Self, to: &Self, amount: f64) -> Self {
+        self + ((to - from) as f64 * amount) as f32
+    }
+}
+
+impl Animatable for Point {
+    fn offset(&self, from: &Self, to: &Self, amount: f64) -> Self {
+        *self + (*to - *from) * amount
+    }
+}
+
+impl Animatable for Color {
+    fn offset(&self, from: &Self, to: &Self, amount: f64) -> Self {
+        Color {
+            red: self.red.offset(&from.red, &to.red, amount),
+            green: self.green.offset(&from.green, &to.green, amount),
+            blue: self.blue.offset(&from.blue, &to.blue, amount),
+        }
+    }
+}
+
+/// How a value changes from one key to the next.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum Interpolation {
+    /// Straight towards the next key, at constant speed.
+    #[default]
+    Linear,
+    /// Cubic Bezier curve whose handles follow the direction between the
+    /// neighbouring keys, so the value passes through keys without sudden
+    /// changes of speed or direction.
+    Bezier,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Key<T> {
+    pub frame: f64,
+    pub value: T,
+    /// Used from this key up to the next one.
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+/// Value of `keys` at `frame`. Before the first key and after the last one
+/// their values hold.
+pub fn evaluate<T: Animatable>(keys: &[Key<T>], frame: f64) -> Option<T> {
+    let (first, last) = (keys.first()?, keys.last()?);
+    if frame <= first.frame {
+        return Some(first.value);
+    }
+    if frame >= last.frame {
+        return Some(last.value);
+    }
+
+    let i = keys.windows(2).position(|pair| frame < pair[1].frame)?;
+    let (a, b) = (&keys[i], &keys[i + 1]);
+    let span = b.frame - a.frame;
+    let t = (frame - a.frame) / span;
+    Some(match a.interpolation {
+        Interpolation::Linear => a.value.lerp(&b.value, t),
+        Interpolation::Bezier => {
+            // Handles a third of the way along Catmull-Rom tangents; the
+            // first and last keys use the segment itself.
+            let before = &keys[i.saturating_sub(1)];
+            let after = &keys[(i + 2).min(keys.len() - 1)];
+            let out_handle = a.value.offset(
+                &before.value,
+                &b.value,
+                span / (3.0 * (b.frame - before.frame)),
+            );
+            let in_handle = b.value.offset(
+                &after.value,
+                &a.value,
+                span / (3.0 * (after.frame - a.frame)),
+            );
+
+            let (p, q, r) = (
+                a.value.lerp(&out_handle, t),
+                out_handle.lerp(&in_handle, t),
+                in_handle.lerp(&b.value, t),
+            );
+            let (p, q) = (p.lerp(&q, t), q.lerp(&r, t));
+            p.lerp(&q, t)
+        }
+    })
+}
+
+/// Property of the scene that changes over the frames of an animation,
+/// with its keys in frame order.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Track {
+    CameraPosition {
+        #[serde(deserialize_with = "ordered_keys")]
+        keys: Vec<Key<Point>>,
+    },
+    CameraLookAt {
+        #[serde(deserialize_with = "ordered_keys")]
+        keys: Vec<Key<Point>>,
+    },
+    /// Offset of the element at index `element` of `Scene::elements` from
+    /// where the scene places it, applied after its own transform.
+    ElementPosition {
+        element: usize,
+        #[serde(deserialize_with = "ordered_keys")]
+        keys: Vec<Key<Point>>,
+    },
+    /// Color of an element's own, untextured material.
+    ElementColor {
+        element: usize,
+        #[serde(deserialize_with = "ordered_keys")]
+        keys: Vec<Key<Color>>,
+    },
+    LightIntensity {
+        light: usize,
+        #[serde(deserialize_with = "ordered_keys")]
+        keys: Vec<Key<f32>>,
+    },
+    LightColor {
+        light: usize,
+        #[serde(deserialize_with = "ordered_keys")]
+        keys: Vec<Key<Color>>,
+    },
+}
+
+impl Track {
+    /// Sets the property to its value at `frame`.
+    pub fn apply(&self, scene: &mut Scene, frame: f64) -> Result<(), String> {
+        match *self {
+            Track::CameraPosition { ref keys } => {
+                set(&mut scene.camera.position, keys, frame);
+            }
+            Track::CameraLookAt { ref keys } => {
+                set(&mut scene.camera.look_at, keys, frame);
+            }
+            Track::ElementPosition { element, ref keys } => {
+                let transform = scene
+                    .elements
+                    .get_mut(element)
+                    .ok_or_else(|| format!("No element {} to animate", element))?
+                    .transform_mut();
+                if let Some(position) = evaluate(keys, frame) {
+                    let offset = position - Point::zero();
+                    *transform = Some(match *transform {
+                        Some(ref t) => t.translated(&offset),
+                        None => Transform::translation(&offset),
+                    });
+                }
+            }
+            Track::ElementColor { element, ref keys } => {
+                let material = scene
+                    .elements
+                    .get_mut(element)
+                    .ok_or_else(|| format!("No element {} to animate", element))?
+                    .material_mut();
+                match material.map(|m| &mut m.coloration) {
+                    Some(Coloration::Color(ref mut color)) => set(color, keys, frame),
+                    _ => return Err(format!("Element {} has no color to animate", element)),
+                }
+            }
+            Track::LightIntensity { light, ref keys } => {
+                let light = light_mut(scene, light)?;
+                set(light.intensity_mut(), keys, frame);
+            }
+            Track::LightColor { light, ref keys } => {
+                let light = light_mut(scene, light)?;
+                set(light.color_mut(), keys, frame);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn light_mut(scene: &mut Scene, light: usize) -> Result<&mut Light, String> {
+    scene
+        .lights
+        .get_mut(light)
+        .ok_or_else(|| format!("No light {} to animate", light))
+}
+
+fn set<T: Animatable>(property: &mut T, keys: &[Key<T>], frame: f64) {
+    if let Some(value) = evaluate(keys, frame) {
+        *property = value;
+    }
+}
+
+fn ordered_keys<'de, D, T>(deserializer: D) -> Result<Vec<Key<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let keys = Vec::<Key<T>>::deserialize(deserializer)?;
+    if keys.windows(2).any(|pair| pair[1].frame < pair[0].frame) {
+        return Err(::serde::de::Error::custom(
+            "Animation keys have to be in frame order",
+        ));
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rendering::Ray, vector::Vector3};
+
+    fn key(frame: f64, value: f32, interpolation: Interpolation) -> Key<f32> {
+        Key {
+            frame,
+            value,
+            interpolation,
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn no_keys_give_no_value() {
+        assert_eq!(evaluate::<f32>(&[], 1.0), None);
+    }
+
+    #[test]
+    fn linear_keys_hold_outside_their_frames() {
+        let keys = [
+            key(10.0, 2.0, Interpolation::Linear),
+            key(20.0, 6.0, Interpolation::Linear),
+        ];
+        assert_eq!(evaluate(&keys, 0.0), Some(2.0));
+        assert_eq!(evaluate(&keys, 10.0), Some(2.0));
+        assert_eq!(evaluate(&keys, 20.0), Some(6.0));
+        assert_eq!(evaluate(&keys, 30.0), Some(6.0));
+        assert!(close(evaluate(&keys, 15.0).unwrap(), 4.0));
+        assert!(close(evaluate(&keys, 12.5).unwrap(), 3.0));
+    }
+
+    #[test]
+    fn bezier_passes_through_keys() {
+        let keys = [
+            key(0.0, 0.0, Interpolation::Bezier),
+            key(4.0, 3.0, Interpolation::Bezier),
+            key(6.0, -1.0, Interpolation::Bezier),
+            key(12.0, 2.0, Interpolation::Bezier),
+        ];
+        for k in &keys {
+            assert!(close(evaluate(&keys, k.frame).unwrap(), k.value));
+            assert!(close(evaluate(&keys, k.frame - 1e-9).unwrap(), k.value));
+        }
+    }
+
+    #[test]
+    fn bezier_speed_is_continuous_through_keys() {
+        let keys = [
+            key(0.0, 0.0, Interpolation::Bezier),
+            key(4.0, 3.0, Interpolation::Bezier),
+            key(6.0, -1.0, Interpolation::Bezier),
+            key(12.0, 2.0, Interpolation::Bezier),
+        ];
+        let h = 1e-3;
+        for k in &keys[1..3] {
+            let value = evaluate(&keys, k.frame).unwrap();
+            let before = (value - evaluate(&keys, k.frame - h).unwrap()) / h as f32;
+            let after = (evaluate(&keys, k.frame + h).unwrap() - value) / h as f32;
+            assert!((before - after).abs() < 1e-2, "{} vs {}", before, after);
+        }
+    }
+
+    #[test]
+    fn keys_out_of_frame_order_are_rejected() {
+        let track = |frames: (f64, f64)| {
+            format!(
+                r#"{{"LightIntensity": {{"light": 0, "keys": [
+                    {{"frame": {}, "value": 1.0}}, {{"frame": {}, "value": 2.0}}]}}}}"#,
+                frames.0, frames.1
+            )
+        };
+        assert!(serde_json::from_str::<Track>(&track((1.0, 2.0))).is_ok());
+        assert!(serde_json::from_str::<Track>(&track((2.0, 1.0))).is_err());
+    }
+
+    #[test]
+    fn instances_move_with_their_position() {
+        let material = serde_json::json!({
+            "coloration": {"Color": {"red": 1.0, "green": 1.0, "blue": 1.0}},
+            "albedo": 0.5,
+            "surface": "Diffuse"
+        });
+        let scene: Scene = serde_json::from_value(serde_json::json!({
+            "width": 1,
+            "height": 1,
+            "fov": 90.0,
+            "geometries": {"ball": {"Sphere": {
+                "center": {"x": 0.0, "y": 0.0, "z": 0.0},
+                "radius": 1.0,
+                "material": material,
+            }}},
+            "elements": [{"Instance": {
+                "geometry": "ball",
+                "transform": [{"Translate": {"x": 0.0, "y": 0.0, "z": -10.0}}],
+            }}],
+            "lights": [],
+            "shadow_bias": 1e-9,
+            "max_recursion_depth": 1,
+            "animation": [{"ElementPosition": {"element": 0, "keys": [
+                {"frame": 0.0, "value": {"x": 0.0, "y": 0.0, "z": 0.0}},
+                {"frame": 10.0, "value": {"x": 0.0, "y": 0.0, "z": 4.0}},
+            ]}}],
+        }))
+        .unwrap();
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            time: 0.0,
+        };
+        for &(frame, distance) in &[(0.0, 9.0), (5.0, 7.0), (20.0, 5.0)] {
+            let mut scene = scene.at_frame(frame).unwrap();
+            scene.resolve_instances().unwrap();
+            let hit = scene.trace(&ray).unwrap();
+            assert!((hit.distance - distance).abs() < 1e-9, "{}", hit.distance);
+        }
+    }
+}
@@ -1,7 +1,15 @@
 use clap::{App, Arg};
 use image::ColorType;
-use raytracer::scene::Scene;
-use std::{fs::File, time, usize};
+use raytracer::{
+    camera::{Projection, View},
+    output::{save_hdr, HdrFormat},
+    scene::Scene,
+};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time,
+};
 
 fn main() {
     let app = App::new("raytracer")
@@ -15,7 +23,33 @@ fn main() {
         .arg(Arg::with_name("image")
             .help("Sets the output image file")
             .required(true)
-            .index(2));
+            .index(2))
+        .arg(Arg::with_name("samples")
+            .long("samples")
+            .takes_value(true)
+            .help("Overrides the scene's samples per pixel"))
+        .arg(Arg::with_name("filter")
+            .long("filter")
+            .takes_value(true)
+            .possible_values(&["box", "tent", "gaussian", "mitchell"])
+            .help("Overrides the scene's reconstruction filter"))
+        .arg(Arg::with_name("tonemap")
+            .long("tonemap")
+            .takes_value(true)
+            .possible_values(&["clamp", "reinhard", "extended-reinhard", "aces", "hable"])
+            .help("Overrides the scene's tone mapping operator"))
+        .arg(Arg::with_name("exposure")
+            .long("exposure")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .help("Overrides the scene's exposure, in stops"))
+        .arg(Arg::with_name("cube-faces")
+            .long("cube-faces")
+            .help("Saves each face of a cubemap to its own file, named after the face"))
+        .arg(Arg::with_name("frames")
+            .long("frames")
+            .takes_value(true)
+            .help("Renders the frames of a range like 0..240 or 1..=240 of the scene's animation, numbering the image files"));
 
     let matches = app.get_matches();
 
@@ -23,8 +57,113 @@ fn main() {
     let scene_file = File::open(scene_path).expect("File not found");
 
     let image_path = matches.value_of("image").unwrap();
-    let scene: Scene = serde_json::from_reader(scene_file).unwrap();
+    let mut scene: Scene = serde_json::from_reader(scene_file).unwrap();
+    if let Some(samples) = matches.value_of("samples") {
+        scene.samples_per_pixel = samples.parse().expect("Invalid sample count");
+    }
+    if let Some(filter) = matches.value_of("filter") {
+        scene.filter = filter.parse().unwrap();
+    }
+    if let Some(operator) = matches.value_of("tonemap") {
+        scene.tone_mapping.operator = operator.parse().unwrap();
+    }
+    if let Some(exposure) = matches.value_of("exposure") {
+        scene.tone_mapping.exposure = exposure.parse().expect("Invalid exposure");
+    }
+
+    let split_faces = matches.is_present("cube-faces");
+    if split_faces && scene.camera.projection != Projection::Cubemap {
+        panic!("--cube-faces needs a camera with the Cubemap projection");
+    }
+
+    let image_path = Path::new(image_path);
+    let frames = match matches.value_of("frames") {
+        Some(range) => parse_frames(range).expect("Invalid frame range"),
+        None => {
+            // Stills show the scene as it is at the start of its animation.
+            let scene = scene.at_frame(0.0).expect("Invalid animation");
+            render_frame(scene, image_path, split_faces);
+            return;
+        }
+    };
+    for frame in frames {
+        println!("Frame {}", frame);
+        let frame_scene = scene.at_frame(frame as f64).expect("Invalid animation");
+        let frame_path = suffixed(image_path, &format!("{:04}", frame));
+        render_frame(frame_scene, &frame_path, split_faces);
+    }
+}
+
+/// Frames of a `start..end` or `start..=end` range.
+fn parse_frames(range: &str) -> Option<Vec<u32>> {
+    if let Some((start, end)) = range.split_once("..=") {
+        return Some((start.parse().ok()?..=end.parse().ok()?).collect());
+    }
+    let (start, end) = range.split_once("..")?;
+    Some((start.parse().ok()?..end.parse().ok()?).collect())
+}
+
+fn render_frame(scene: Scene, image_path: &Path, split_faces: bool) {
+    let outputs = outputs(&scene, image_path, split_faces);
+    let hdr_format = HdrFormat::from_path(image_path);
+    match hdr_format {
+        Some(format) => render_hdr_image(scene, &outputs, format),
+        None => render_image(scene, &outputs),
+    }
+}
+
+/// `path` with `_suffix` added to the file name, before the extension.
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push("_");
+    name.push(suffix);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Files to save and the part of the frame each one shows: the whole frame
+/// at `path`, or with `split_faces`, every cubemap face next to it.
+fn outputs(scene: &Scene, path: &Path, split_faces: bool) -> Vec<(PathBuf, View)> {
+    if !split_faces {
+        let whole = View {
+            left: 0,
+            top: 0,
+            width: scene.width,
+            height: scene.height,
+            eye: None,
+            face: None,
+        };
+        return vec![(path.to_path_buf(), whole)];
+    }
+
+    scene
+        .camera
+        .views(scene.width, scene.height)
+        .into_iter()
+        .map(|view| {
+            let eye = view.eye.map(|e| e.name());
+            let parts: Vec<&str> = eye.into_iter().chain(view.face.map(|f| f.name())).collect();
+            (suffixed(path, &parts.join("_")), view)
+        })
+        .collect()
+}
+
+/// Copies the pixels of `view` out of a frame `width` pixels wide.
+fn crop<T: Copy>(pixels: &[T], channels: usize, width: u32, view: &View) -> Vec<T> {
+    (view.top..view.top + view.height)
+        .flat_map(|y| {
+            let start = (y * width + view.left) as usize * channels;
+            pixels[start..start + view.width as usize * channels]
+                .iter()
+                .cloned()
+        })
+        .collect()
+}
 
+fn render_image(scene: Scene, outputs: &[(PathBuf, View)]) {
     let width = scene.width;
     let height = scene.height;
     let color_type = ColorType::Rgb8;
@@ -32,20 +171,80 @@ fn main() {
 
     let num_bytes = width as usize * height as usize * bytes_per_pixel as usize;
     let mut buf = vec![0; num_bytes];
-    let mut buf = &mut buf[..];
+    let buf = &mut buf[..];
 
     let start = time::Instant::now();
     println!("Starting rendering at {:?}", start);
-    raytracer::render(scene, &mut buf, bytes_per_pixel);
+    if let Err(e) = raytracer::render(scene, buf, bytes_per_pixel) {
+        println!("Failed to render scene: {}", e);
+        return;
+    }
     let dur = time::Instant::now() - start;
     println!("Finished rendering.\nRender time: {:?}\n", dur);
 
     let start = time::Instant::now();
     println!("Starting file save at {:?}", start);
-    if let Some(e) = image::save_buffer(image_path, &buf, width, height, color_type).err() {
-        println!("Failed to save image: {}", e);
-    } else {
-        let dur = time::Instant::now() - start;
-        println!("Finished saving.\nSave time: {:?}\n", dur);
+    for (path, view) in outputs {
+        let pixels = crop(buf, bytes_per_pixel as usize, width, view);
+        let saved = image::save_buffer(path, &pixels, view.width, view.height, color_type);
+        if let Some(e) = saved.err() {
+            println!("Failed to save image {:?}: {}", path, e);
+            return;
+        }
+    }
+    let dur = time::Instant::now() - start;
+    println!("Finished saving.\nSave time: {:?}\n", dur);
+}
+
+fn render_hdr_image(scene: Scene, outputs: &[(PathBuf, View)], format: HdrFormat) {
+    let width = scene.width;
+
+    let start = time::Instant::now();
+    println!("Starting rendering at {:?}", start);
+    let colors = match raytracer::render_hdr(scene) {
+        Ok(colors) => colors,
+        Err(e) => {
+            println!("Failed to render scene: {}", e);
+            return;
+        }
+    };
+    let dur = time::Instant::now() - start;
+    println!("Finished rendering.\nRender time: {:?}\n", dur);
+
+    let start = time::Instant::now();
+    println!("Starting file save at {:?}", start);
+    for (path, view) in outputs {
+        let pixels = crop(&colors, 1, width, view);
+        if let Err(e) = save_hdr(path, format, &pixels, view.width, view.height) {
+            println!("Failed to save image {:?}: {}", path, e);
+            return;
+        }
+    }
+    let dur = time::Instant::now() - start;
+    println!("Finished saving.\nSave time: {:?}\n", dur);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_frames;
+
+    #[test]
+    fn exclusive_range_stops_before_end() {
+        assert_eq!(parse_frames("0..3"), Some(vec![0, 1, 2]));
+        assert_eq!(parse_frames("3..3"), Some(vec![]));
+    }
+
+    #[test]
+    fn inclusive_range_keeps_end() {
+        assert_eq!(parse_frames("1..=3"), Some(vec![1, 2, 3]));
+        assert_eq!(parse_frames("3..=3"), Some(vec![3]));
+    }
+
+    #[test]
+    fn malformed_ranges_are_rejected() {
+        assert_eq!(parse_frames("3"), None);
+        assert_eq!(parse_frames("..3"), None);
+        assert_eq!(parse_frames("1..=x"), None);
+        assert_eq!(parse_frames("-1..3"), None);
     }
 }
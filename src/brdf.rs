@@ -0,0 +1,282 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use crate::{
+    color::{Color, BLACK},
+    element::Element,
+    material::SurfaceType,
+    rendering::TextureCoords,
+    sampling::{cosine_hemisphere, tangent_frame},
+    vector::Vector3,
+};
+
+/// Smallest GGX alpha, so smooth surfaces keep a finite highlight.
+const MIN_ALPHA: f64 = 1e-3;
+/// Lowest probability of sampling the specular lobe of a `Principled` BRDF.
+const MIN_SPECULAR_PROBABILITY: f32 = 0.1;
+
+/// Reflectance of an opaque surface at one point, with every texture already
+/// looked up.
+///
+/// All directions point away from the surface: `outgoing` towards the viewer
+/// and `incoming` towards the light.
+pub enum Brdf {
+    /// Ideal diffuse reflector, already scaled by the albedo, with an optional
+    /// Blinn-Phong highlight.
+    Lambert {
+        color: Color,
+        highlight: Option<Highlight>,
+    },
+    Principled {
+        base_color: Color,
+        metallic: f32,
+        roughness: f32,
+        specular: f32,
+    },
+}
+
+/// Normalized Blinn-Phong lobe.
+#[derive(Clone, Copy)]
+pub struct Highlight {
+    pub color: Color,
+    pub shininess: f32,
+}
+
+impl Highlight {
+    fn eval(&self, normal: &Vector3, outgoing: &Vector3, incoming: &Vector3) -> Color {
+        let half = (*outgoing + *incoming).normalize();
+        let cos_h = normal.dot(&half);
+        if cos_h <= 0.0 || normal.dot(outgoing) <= 0.0 {
+            return BLACK;
+        }
+        // Keeps the reflected energy roughly constant as the lobe narrows.
+        let shininess = self.shininess as f64;
+        let normalization = (shininess + 8.0) / (8.0 * PI);
+        self.color * (normalization * cos_h.powf(shininess)) as f32
+    }
+}
+
+/// A direction picked by `Brdf::sample`.
+pub struct BrdfSample {
+    pub direction: Vector3,
+    /// BRDF times cosine over pdf, which scales the path throughput.
+    pub weight: Color,
+    pub pdf: f64,
+}
+
+impl Brdf {
+    /// The BRDF of `element` at a point; the non-diffuse parts of mirrors and
+    /// dielectrics are handled by the integrators themselves.
+    pub fn at(element: &Element, coords: &TextureCoords) -> Self {
+        match element.material().surface {
+            SurfaceType::Principled {
+                ref base_color,
+                ref metallic,
+                ref roughness,
+                specular,
+            } => Brdf::Principled {
+                base_color: base_color.color(coords),
+                metallic: metallic.value(coords).clamp(0.0, 1.0),
+                roughness: roughness.value(coords).clamp(0.0, 1.0),
+                specular,
+            },
+            _ => {
+                let material = element.material();
+                Brdf::Lambert {
+                    color: element.color(coords) * element.albedo(),
+                    highlight: material.specular_color.map(|color| Highlight {
+                        color,
+                        shininess: material.shininess,
+                    }),
+                }
+            }
+        }
+    }
+
+    pub fn eval(&self, normal: &Vector3, outgoing: &Vector3, incoming: &Vector3) -> Color {
+        let cos_i = normal.dot(incoming);
+        if cos_i <= 0.0 {
+            return BLACK;
+        }
+
+        match *self {
+            Brdf::Lambert { color, highlight } => {
+                let diffuse = color * (1.0 / PI) as f32;
+                match highlight {
+                    Some(h) => diffuse + h.eval(normal, outgoing, incoming),
+                    None => diffuse,
+                }
+            }
+            Brdf::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+            } => {
+                let f0 = specular_color(base_color, metallic, specular);
+                let cos_o = normal.dot(outgoing);
+                let (glossy, fresnel) = if cos_o > 0.0 {
+                    let half = (*outgoing + *incoming).normalize();
+                    let alpha = alpha(roughness);
+                    let fresnel = schlick(f0, outgoing.dot(&half));
+                    let d = ggx(normal.dot(&half), alpha);
+                    let g = smith_g1(cos_o, alpha) * smith_g1(cos_i, alpha);
+                    (fresnel * (d * g / (4.0 * cos_o * cos_i)) as f32, fresnel)
+                } else {
+                    (BLACK, f0)
+                };
+                // Light not reflected by the coating reaches the diffuse base.
+                let transmitted = Color {
+                    red: 1.0 - fresnel.red,
+                    green: 1.0 - fresnel.green,
+                    blue: 1.0 - fresnel.blue,
+                };
+                let diffuse = base_color * transmitted * ((1.0 - metallic) / PI as f32);
+                diffuse + glossy
+            }
+        }
+    }
+
+    /// Density with which `sample` picks `incoming`.
+    pub fn pdf(&self, normal: &Vector3, outgoing: &Vector3, incoming: &Vector3) -> f64 {
+        let cos_i = normal.dot(incoming);
+        if cos_i <= 0.0 {
+            return 0.0;
+        }
+        let diffuse_pdf = cos_i / PI;
+
+        match *self {
+            Brdf::Lambert { .. } => diffuse_pdf,
+            Brdf::Principled { roughness, .. } => {
+                let half = (*outgoing + *incoming).normalize();
+                let cos_oh = outgoing.dot(&half);
+                let glossy_pdf = if cos_oh > 0.0 {
+                    let cos_h = normal.dot(&half);
+                    ggx(cos_h, alpha(roughness)) * cos_h / (4.0 * cos_oh)
+                } else {
+                    0.0
+                };
+                let p = self.specular_probability() as f64;
+                p * glossy_pdf + (1.0 - p) * diffuse_pdf
+            }
+        }
+    }
+
+    /// Picks the direction of the next bounce, proportionally to the BRDF
+    /// where that's possible.
+    pub fn sample<R: Rng>(
+        &self,
+        normal: &Vector3,
+        outgoing: &Vector3,
+        rng: &mut R,
+    ) -> Option<BrdfSample> {
+        let direction = match *self {
+            Brdf::Lambert {
+                color,
+                highlight: None,
+            } => {
+                let direction = cosine_hemisphere(normal, rng);
+                return Some(BrdfSample {
+                    direction,
+                    weight: color,
+                    pdf: normal.dot(&direction).max(0.0) / PI,
+                });
+            }
+            // Highlights are only picked up through the cosine-weighted bounce,
+            // which is noisy but unbiased.
+            Brdf::Lambert { .. } => cosine_hemisphere(normal, rng),
+            Brdf::Principled { roughness, .. } => {
+                if rng.gen::<f32>() < self.specular_probability() {
+                    let half = sample_ggx(normal, alpha(roughness), rng.gen(), rng.gen());
+                    half * (2.0 * outgoing.dot(&half)) - *outgoing
+                } else {
+                    cosine_hemisphere(normal, rng)
+                }
+            }
+        };
+
+        let pdf = self.pdf(normal, outgoing, &direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let cos_i = normal.dot(&direction);
+        Some(BrdfSample {
+            direction,
+            weight: self.eval(normal, outgoing, &direction) * (cos_i / pdf) as f32,
+            pdf,
+        })
+    }
+
+    /// Probability of sampling the glossy rather than the diffuse lobe,
+    /// roughly in proportion to how much each reflects.
+    fn specular_probability(&self) -> f32 {
+        match *self {
+            Brdf::Lambert { .. } => 0.0,
+            Brdf::Principled {
+                base_color,
+                metallic,
+                specular,
+                ..
+            } => {
+                let glossy = specular_color(base_color, metallic, specular).luminance();
+                let diffuse = (1.0 - metallic) * base_color.luminance();
+                if glossy + diffuse <= 0.0 {
+                    0.5
+                } else {
+                    (glossy / (glossy + diffuse)).clamp(MIN_SPECULAR_PROBABILITY, 1.0)
+                }
+            }
+        }
+    }
+}
+
+fn alpha(roughness: f32) -> f64 {
+    (roughness as f64 * roughness as f64).max(MIN_ALPHA)
+}
+
+/// Reflectance at normal incidence: tinted by the base color for metals.
+pub fn specular_color(base_color: Color, metallic: f32, specular: f32) -> Color {
+    let dielectric = 0.08 * specular;
+    Color {
+        red: dielectric + (base_color.red - dielectric) * metallic,
+        green: dielectric + (base_color.green - dielectric) * metallic,
+        blue: dielectric + (base_color.blue - dielectric) * metallic,
+    }
+}
+
+fn schlick(f0: Color, cos_theta: f64) -> Color {
+    let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5) as f32;
+    Color {
+        red: f0.red + (1.0 - f0.red) * weight,
+        green: f0.green + (1.0 - f0.green) * weight,
+        blue: f0.blue + (1.0 - f0.blue) * weight,
+    }
+}
+
+/// GGX (Trowbridge-Reitz) normal distribution.
+fn ggx(cos_h: f64, alpha: f64) -> f64 {
+    if cos_h <= 0.0 {
+        return 0.0;
+    }
+    let alpha2 = alpha * alpha;
+    let denom = cos_h * cos_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * denom * denom)
+}
+
+/// Smith masking for one direction, matching `ggx`.
+fn smith_g1(cos_theta: f64, alpha: f64) -> f64 {
+    let alpha2 = alpha * alpha;
+    2.0 * cos_theta / (cos_theta + (alpha2 + (1.0 - alpha2) * cos_theta * cos_theta).sqrt())
+}
+
+/// Half vector distributed with a pdf of `ggx(cos_h) * cos_h`.
+fn sample_ggx(normal: &Vector3, alpha: f64, u: f64, v: f64) -> Vector3 {
+    let alpha2 = alpha * alpha;
+    let cos2 = (1.0 - u) / (1.0 + (alpha2 - 1.0) * u);
+    let cos_h = cos2.sqrt();
+    let sin_h = (1.0 - cos2).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    let (tangent, bitangent) = tangent_frame(normal);
+    (tangent * (sin_h * phi.cos()) + bitangent * (sin_h * phi.sin()) + *normal * cos_h).normalize()
+}
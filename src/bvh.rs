@@ -0,0 +1,687 @@
+use crate::{
+    element::{
+        AxisAlignedBox, Cone, Csg, CsgOperation, Cylinder, Disk, Element, Plane, Sphere, Torus,
+    },
+    mesh::Mesh,
+    point::Point,
+    rendering::Ray,
+    vector::{Matrix4, Vector3},
+};
+
+/// Number of buckets centroids are binned into when evaluating SAH splits.
+const SAH_BUCKETS: usize = 12;
+/// Relative cost of a ray/box test compared to a ray/primitive test.
+const TRAVERSAL_COST: f64 = 0.125;
+const MAX_LEAF_SIZE: usize = 4;
+/// Times at which moving elements are bounded across the shutter interval.
+const MOTION_BOUND_STEPS: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+impl BoundingBox {
+    pub fn empty() -> Self {
+        Self {
+            min: Point::from_one(f64::INFINITY),
+            max: Point::from_one(f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn infinite() -> Self {
+        Self {
+            min: Point::from_one(f64::NEG_INFINITY),
+            max: Point::from_one(f64::INFINITY),
+        }
+    }
+
+    pub fn from_points<I: IntoIterator<Item = Point>>(points: I) -> Self {
+        points
+            .into_iter()
+            .fold(Self::empty(), |bounds, p| bounds.include(&p))
+    }
+
+    /// Box around the eight transformed corners. Empty and infinite boxes
+    /// are returned unchanged.
+    pub fn transform(&self, matrix: &Matrix4) -> Self {
+        if !self.is_finite() {
+            return *self;
+        }
+        Self::from_points(self.corners().iter().map(|p| matrix.transform_point(p)))
+    }
+
+    pub fn corners(&self) -> [Point; 8] {
+        let mut corners = [self.min; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            *corner = Point {
+                x: if i & 1 == 0 { self.min.x } else { self.max.x },
+                y: if i & 2 == 0 { self.min.y } else { self.max.y },
+                z: if i & 4 == 0 { self.min.z } else { self.max.z },
+            };
+        }
+        corners
+    }
+
+    pub fn is_finite(&self) -> bool {
+        [
+            self.min.x, self.min.y, self.min.z, self.max.x, self.max.y, self.max.z,
+        ]
+        .iter()
+        .all(|v| v.is_finite())
+    }
+
+    pub fn include(&self, p: &Point) -> Self {
+        Self {
+            min: Point {
+                x: self.min.x.min(p.x),
+                y: self.min.y.min(p.y),
+                z: self.min.z.min(p.z),
+            },
+            max: Point {
+                x: self.max.x.max(p.x),
+                y: self.max.y.max(p.y),
+                z: self.max.z.max(p.z),
+            },
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        self.include(&other.min).include(&other.max)
+    }
+
+    pub fn extent(&self) -> Vector3 {
+        self.max - self.min
+    }
+
+    pub fn centroid(&self) -> Point {
+        self.min + self.extent() * 0.5
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let e = self.extent();
+        if e.x < 0.0 || e.y < 0.0 || e.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    /// Slab test; returns the distance at which the ray enters the box, or
+    /// `None` if it misses it or the box is further away than `max_distance`.
+    pub fn intersect(&self, ray: &Ray, max_distance: f64) -> Option<f64> {
+        let mut t_min: f64 = 0.0;
+        let mut t_max = max_distance;
+        let axes = [
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ];
+        for &(origin, direction, min, max) in axes.iter() {
+            let inv = direction.recip();
+            let mut t0 = (min - origin) * inv;
+            let mut t1 = (max - origin) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN (ray parallel to and exactly on a slab) compares false and
+            // is ignored here, which keeps such rays inside the box.
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+}
+
+pub trait Bounded {
+    fn bounding_box(&self) -> BoundingBox;
+}
+
+#[derive(Clone, Debug)]
+enum BvhNode {
+    Leaf {
+        bounds: BoundingBox,
+        first: usize,
+        count: usize,
+    },
+    Interior {
+        bounds: BoundingBox,
+        left: usize,
+        right: usize,
+    },
+}
+impl BvhNode {
+    fn bounds(&self) -> &BoundingBox {
+        match *self {
+            BvhNode::Leaf { ref bounds, .. } => bounds,
+            BvhNode::Interior { ref bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Bounding volume hierarchy over primitives identified by their index.
+///
+/// Primitives with unbounded extent (e.g. planes) can't be partitioned and
+/// are kept aside and tested on every query.
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+struct BuildItem {
+    index: usize,
+    bounds: BoundingBox,
+    centroid: Point,
+}
+
+fn axis_value(p: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => p.x,
+        1 => p.y,
+        _ => p.z,
+    }
+}
+
+impl Bvh {
+    /// Builds the hierarchy using the surface area heuristic.
+    pub fn build(bounds: &[BoundingBox]) -> Self {
+        let mut bvh = Bvh::default();
+        let mut items = Vec::with_capacity(bounds.len());
+        for (index, b) in bounds.iter().enumerate() {
+            if b.is_finite() {
+                items.push(BuildItem {
+                    index,
+                    bounds: *b,
+                    centroid: b.centroid(),
+                });
+            } else {
+                bvh.unbounded.push(index);
+            }
+        }
+
+        if !items.is_empty() {
+            bvh.build_node(&mut items);
+        }
+        bvh
+    }
+
+    fn build_node(&mut self, items: &mut [BuildItem]) -> usize {
+        let bounds = items
+            .iter()
+            .fold(BoundingBox::empty(), |b, item| b.union(&item.bounds));
+        let node_index = self.nodes.len();
+
+        let split = if items.len() > 1 {
+            Self::find_split(items, &bounds)
+        } else {
+            None
+        };
+
+        match split {
+            Some(mid) => {
+                // Reserve our slot before the children so the root stays at 0.
+                self.nodes.push(BvhNode::Leaf {
+                    bounds,
+                    first: 0,
+                    count: 0,
+                });
+                let (lower, upper) = items.split_at_mut(mid);
+                let left = self.build_node(lower);
+                let right = self.build_node(upper);
+                self.nodes[node_index] = BvhNode::Interior {
+                    bounds,
+                    left,
+                    right,
+                };
+            }
+            None => {
+                let first = self.indices.len();
+                self.indices.extend(items.iter().map(|item| item.index));
+                self.nodes.push(BvhNode::Leaf {
+                    bounds,
+                    first,
+                    count: items.len(),
+                });
+            }
+        }
+        node_index
+    }
+
+    /// Partitions `items` along the cheapest SAH split and returns the split
+    /// position, or `None` if a leaf is cheaper.
+    fn find_split(items: &mut [BuildItem], bounds: &BoundingBox) -> Option<usize> {
+        let centroid_bounds = items
+            .iter()
+            .fold(BoundingBox::empty(), |b, item| b.include(&item.centroid));
+        let extent = centroid_bounds.extent();
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let axis_min = axis_value(&centroid_bounds.min, axis);
+        let axis_extent = axis_value(&centroid_bounds.max, axis) - axis_min;
+
+        if axis_extent <= 0.0 {
+            // All centroids coincide; SAH can't separate them.
+            return if items.len() > MAX_LEAF_SIZE {
+                Some(items.len() / 2)
+            } else {
+                None
+            };
+        }
+
+        let bucket_of = |item: &BuildItem| {
+            let offset = (axis_value(&item.centroid, axis) - axis_min) / axis_extent;
+            ((offset * SAH_BUCKETS as f64) as usize).min(SAH_BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; SAH_BUCKETS];
+        let mut bucket_bounds = [BoundingBox::empty(); SAH_BUCKETS];
+        for item in items.iter() {
+            let b = bucket_of(item);
+            counts[b] += 1;
+            bucket_bounds[b] = bucket_bounds[b].union(&item.bounds);
+        }
+
+        let total_area = bounds.surface_area();
+        let mut best: Option<(f64, usize)> = None;
+        for split in 1..SAH_BUCKETS {
+            let (below, above) = (&bucket_bounds[..split], &bucket_bounds[split..]);
+            let count_below: usize = counts[..split].iter().sum();
+            let count_above: usize = counts[split..].iter().sum();
+            if count_below == 0 || count_above == 0 {
+                continue;
+            }
+            let area_below = below
+                .iter()
+                .fold(BoundingBox::empty(), |acc, b| acc.union(b))
+                .surface_area();
+            let area_above = above
+                .iter()
+                .fold(BoundingBox::empty(), |acc, b| acc.union(b))
+                .surface_area();
+            let cost = TRAVERSAL_COST
+                + (count_below as f64 * area_below + count_above as f64 * area_above) / total_area;
+            if best.is_none_or(|(c, _)| cost < c) {
+                best = Some((cost, split));
+            }
+        }
+
+        let (cost, split) = best?;
+        if cost >= items.len() as f64 && items.len() <= MAX_LEAF_SIZE {
+            return None;
+        }
+
+        items.sort_by_key(|item| bucket_of(item) >= split);
+        Some(items.iter().filter(|item| bucket_of(item) < split).count())
+    }
+
+    /// Bounds of all the bounded primitives, if there are any.
+    pub fn bounds(&self) -> Option<&BoundingBox> {
+        self.nodes.first().map(|node| node.bounds())
+    }
+
+    /// Finds the closest primitive hit by `ray`, using `intersect` to test
+    /// individual primitives. Whatever else `intersect` reports about the
+    /// hit is passed on along with its distance.
+    pub fn nearest<F, T>(&self, ray: &Ray, intersect: F) -> Option<(usize, f64, T)>
+    where
+        F: Fn(usize) -> Option<(f64, T)>,
+    {
+        let mut closest: Option<(usize, f64, T)> = None;
+        for &index in &self.unbounded {
+            if let Some((d, hit)) = intersect(index) {
+                if closest.as_ref().is_none_or(|&(_, c, _)| d < c) {
+                    closest = Some((index, d, hit));
+                }
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let max_distance = closest.as_ref().map_or(f64::INFINITY, |&(_, d, _)| d);
+            if node.bounds().intersect(ray, max_distance).is_none() {
+                continue;
+            }
+
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for &index in &self.indices[first..first + count] {
+                        if let Some((d, hit)) = intersect(index) {
+                            if closest.as_ref().is_none_or(|&(_, c, _)| d < c) {
+                                closest = Some((index, d, hit));
+                            }
+                        }
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+        closest
+    }
+
+    /// Calls `visit` with every primitive whose bounds `ray` passes through.
+    pub fn intersecting<F>(&self, ray: &Ray, mut visit: F)
+    where
+        F: FnMut(usize),
+    {
+        for &index in &self.unbounded {
+            visit(index);
+        }
+
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.bounds().intersect(ray, f64::INFINITY).is_none() {
+                continue;
+            }
+
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for &index in &self.indices[first..first + count] {
+                        visit(index);
+                    }
+                }
+                BvhNode::Interior { left, right, .. } => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+    }
+}
+
+impl Bounded for Element {
+    fn bounding_box(&self) -> BoundingBox {
+        let bounds = self.object_bounds();
+        match self.transform() {
+            Some(t) => bounds.transform(&t.affine.matrix),
+            None => bounds,
+        }
+    }
+}
+
+impl Element {
+    /// Box around everywhere the element is between `start` and `end`.
+    ///
+    /// Moving elements are bounded at evenly spaced times, and the boxes
+    /// grown by half the largest distance a corner travels between two of
+    /// them, which covers the arcs that rotating corners follow in between.
+    pub fn swept_bounds(&self, start: f64, end: f64) -> BoundingBox {
+        let bounds = self.object_bounds();
+        if self.motion().is_none() || !bounds.is_finite() {
+            return self.bounding_box();
+        }
+
+        let corners = bounds.corners();
+        let mut swept = BoundingBox::empty();
+        let mut previous: Option<Vec<Point>> = None;
+        let mut largest_step: f64 = 0.0;
+        for step in 0..=MOTION_BOUND_STEPS {
+            let time = start + (end - start) * step as f64 / MOTION_BOUND_STEPS as f64;
+            let transform = self.transform_at(time).unwrap();
+            let moved: Vec<Point> = corners
+                .iter()
+                .map(|c| transform.point_to_world(c))
+                .collect();
+            if let Some(previous) = previous {
+                for (a, b) in previous.iter().zip(moved.iter()) {
+                    largest_step = largest_step.max((*b - *a).length());
+                }
+            }
+            swept = swept.union(&BoundingBox::from_points(moved.iter().cloned()));
+            previous = Some(moved);
+        }
+
+        let margin = Vector3::from_one(largest_step / 2.0);
+        BoundingBox {
+            min: swept.min + -margin,
+            max: swept.max + margin,
+        }
+    }
+
+    fn object_bounds(&self) -> BoundingBox {
+        match *self {
+            Element::Sphere(ref s) => s.bounding_box(),
+            Element::Plane(ref p) => p.bounding_box(),
+            Element::Mesh(ref m) => m.bounding_box(),
+            Element::Instance(ref i) => i.geometry().bounding_box(),
+            Element::Box(ref b) => b.bounding_box(),
+            Element::Cylinder(ref c) => c.bounding_box(),
+            Element::Cone(ref c) => c.bounding_box(),
+            Element::Disk(ref d) => d.bounding_box(),
+            Element::Torus(ref t) => t.bounding_box(),
+            Element::Csg(ref c) => c.bounding_box(),
+            Element::Sdf(ref s) => s.shape.bounds(),
+            Element::Heightfield(ref h) => h.bounds(),
+        }
+    }
+}
+
+impl Bounded for Sphere {
+    fn bounding_box(&self) -> BoundingBox {
+        let r = Vector3::from_one(self.radius);
+        BoundingBox {
+            min: self.center + -r,
+            max: self.center + r,
+        }
+    }
+}
+
+impl Bounded for Plane {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::infinite()
+    }
+}
+
+impl Bounded for AxisAlignedBox {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::from_points(vec![self.min, self.max])
+    }
+}
+
+impl Bounded for Cylinder {
+    fn bounding_box(&self) -> BoundingBox {
+        let r = self.radius;
+        BoundingBox::from_points(vec![
+            self.center
+                + Vector3 {
+                    x: -r,
+                    y: 0.0,
+                    z: -r,
+                },
+            self.center
+                + Vector3 {
+                    x: r,
+                    y: self.height,
+                    z: r,
+                },
+        ])
+    }
+}
+
+impl Bounded for Cone {
+    fn bounding_box(&self) -> BoundingBox {
+        let r = self.radius;
+        BoundingBox::from_points(vec![
+            self.center
+                + Vector3 {
+                    x: -r,
+                    y: 0.0,
+                    z: -r,
+                },
+            self.center
+                + Vector3 {
+                    x: r,
+                    y: self.height,
+                    z: r,
+                },
+        ])
+    }
+}
+
+impl Bounded for Disk {
+    fn bounding_box(&self) -> BoundingBox {
+        let n = &self.normal;
+        // Extent of a circle along each axis.
+        let half = Vector3 {
+            x: (1.0 - n.x * n.x).max(0.0).sqrt(),
+            y: (1.0 - n.y * n.y).max(0.0).sqrt(),
+            z: (1.0 - n.z * n.z).max(0.0).sqrt(),
+        } * self.radius;
+        BoundingBox::from_points(vec![self.center + (-half), self.center + half])
+    }
+}
+
+impl Bounded for Torus {
+    fn bounding_box(&self) -> BoundingBox {
+        let outer = self.major_radius + self.minor_radius;
+        let half = Vector3 {
+            x: outer,
+            y: self.minor_radius,
+            z: outer,
+        };
+        BoundingBox::from_points(vec![self.center + (-half), self.center + half])
+    }
+}
+
+impl Bounded for Csg {
+    fn bounding_box(&self) -> BoundingBox {
+        let left = self.left.bounding_box();
+        let right = self.right.bounding_box();
+        match self.op {
+            CsgOperation::Union => left.union(&right),
+            CsgOperation::Intersection => BoundingBox {
+                min: Point {
+                    x: left.min.x.max(right.min.x),
+                    y: left.min.y.max(right.min.y),
+                    z: left.min.z.max(right.min.z),
+                },
+                max: Point {
+                    x: left.max.x.min(right.max.x),
+                    y: left.max.y.min(right.max.y),
+                    z: left.max.z.min(right.max.z),
+                },
+            },
+            CsgOperation::Difference => left,
+        }
+    }
+}
+
+impl Bounded for Mesh {
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox::from_points(self.positions.iter().cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+    use serde_json::json;
+
+    use crate::{point::Point, rendering::Ray, scene::Scene, vector::Vector3};
+
+    fn random_point<R: Rng>(rng: &mut R, range: f64) -> Point {
+        Point {
+            x: rng.gen_range(-range..range),
+            y: rng.gen_range(-range..range),
+            z: rng.gen_range(-range..range),
+        }
+    }
+
+    fn random_scene<R: Rng>(rng: &mut R) -> Scene {
+        let material = json!({
+            "coloration": {"Color": {"red": 1.0, "green": 1.0, "blue": 1.0}},
+            "albedo": 0.5,
+            "surface": "Diffuse"
+        });
+        let mut elements = vec![json!({"Plane": {
+            "origin": {"x": 0.0, "y": -12.0, "z": 0.0},
+            "normal": {"x": 0.0, "y": -1.0, "z": 0.0},
+            "material": material,
+        }})];
+        for _ in 0..40 {
+            let center = random_point(rng, 10.0);
+            elements.push(json!({"Sphere": {
+                "center": center,
+                "radius": rng.gen_range(0.2..2.0),
+                "material": material,
+            }}));
+            let corner = random_point(rng, 10.0);
+            elements.push(json!({"Box": {
+                "min": corner,
+                "max": corner + Vector3 {
+                    x: rng.gen_range(0.1..3.0),
+                    y: rng.gen_range(0.1..3.0),
+                    z: rng.gen_range(0.1..3.0),
+                },
+                "material": material,
+            }}));
+        }
+        let mut scene: Scene = serde_json::from_value(json!({
+            "width": 1,
+            "height": 1,
+            "fov": 90.0,
+            "elements": elements,
+            "lights": [],
+            "shadow_bias": 1e-9,
+            "max_recursion_depth": 1,
+        }))
+        .unwrap();
+        scene.build_bvh();
+        scene
+    }
+
+    #[test]
+    fn nearest_matches_linear_search() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let scene = random_scene(&mut rng);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray {
+                origin: random_point(&mut rng, 15.0),
+                direction: (random_point(&mut rng, 1.0) - Point::zero()).normalize(),
+                time: 0.0,
+            };
+            let fast = scene.trace(&ray);
+            let linear = scene.trace_linear(&ray);
+            match (fast, linear) {
+                (None, None) => {}
+                (Some(fast), Some(linear)) => {
+                    hits += 1;
+                    assert!(std::ptr::eq(fast.element, linear.element));
+                    assert_eq!(fast.distance, linear.distance);
+                }
+                (fast, linear) => panic!(
+                    "{:?} vs {:?}",
+                    fast.map(|i| i.distance),
+                    linear.map(|i| i.distance)
+                ),
+            }
+        }
+        assert!(hits > 1000, "only {} rays hit", hits);
+    }
+}
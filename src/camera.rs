@@ -0,0 +1,401 @@
+use std::f64::consts::PI;
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{point::Point, sampling::concentric_disk, vector::Vector3};
+
+/// Orthonormal basis the camera looks through.
+pub struct CameraBasis {
+    pub right: Vector3,
+    pub up: Vector3,
+    pub forward: Vector3,
+}
+
+/// How directions around the camera are laid out on the image.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum Projection {
+    /// Pinhole projection covering the camera's `fov`.
+    #[default]
+    Perspective,
+    /// Parallel rays from a plane `view_width` wide; the height follows the
+    /// aspect ratio of the image.
+    Orthographic { view_width: f64 },
+    /// Circular image fitted to the shorter side of the frame, covering
+    /// `fov` degrees across. Pixels outside the circle stay black.
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+    /// Full 360° by 180° panorama, with the viewing direction in the
+    /// middle.
+    Equirectangular,
+    /// The six 90° faces of a cube around the camera, unfolded into a
+    /// horizontal cross four faces wide and three high. The faces are as
+    /// large as the frame allows; pixels outside them stay black.
+    Cubemap,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance from the center proportional to the angle.
+    Equidistant,
+    /// Preserves areas, like most real fisheye lenses.
+    Equisolid,
+}
+
+/// Renders the scene once for each eye, splitting the frame between them.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Stereo {
+    /// Distance between the eyes, which sit on either side of the camera
+    /// position.
+    pub interocular_distance: f64,
+    /// Distance at which both eyes see the same image, so that things there
+    /// appear at the depth of the screen. Defaults to the focus distance.
+    #[serde(default)]
+    pub convergence: Option<f64>,
+    #[serde(default)]
+    pub layout: StereoLayout,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye in the left half of the frame.
+    #[default]
+    SideBySide,
+    /// Left eye in the top half of the frame.
+    TopBottom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    pub fn name(self) -> &'static str {
+        match self {
+            Eye::Left => "left",
+            Eye::Right => "right",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CubeFace {
+    Right,
+    Left,
+    Up,
+    Down,
+    Front,
+    Back,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::Right,
+        CubeFace::Left,
+        CubeFace::Up,
+        CubeFace::Down,
+        CubeFace::Front,
+        CubeFace::Back,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CubeFace::Right => "right",
+            CubeFace::Left => "left",
+            CubeFace::Up => "up",
+            CubeFace::Down => "down",
+            CubeFace::Front => "front",
+            CubeFace::Back => "back",
+        }
+    }
+
+    /// Column and row of the face in the cross layout. Faces that touch in
+    /// the cross share the same edge on the cube.
+    pub fn cross_cell(self) -> (u32, u32) {
+        match self {
+            CubeFace::Up => (1, 0),
+            CubeFace::Left => (0, 1),
+            CubeFace::Front => (1, 1),
+            CubeFace::Right => (2, 1),
+            CubeFace::Back => (3, 1),
+            CubeFace::Down => (1, 2),
+        }
+    }
+
+    /// Directions the face looks through, relative to the camera's.
+    pub fn basis(self, camera: &CameraBasis) -> CameraBasis {
+        let (right, up, forward) = (camera.right, camera.up, camera.forward);
+        let (right, up, forward) = match self {
+            CubeFace::Front => (right, up, forward),
+            CubeFace::Right => (-forward, up, right),
+            CubeFace::Back => (-right, up, -forward),
+            CubeFace::Left => (forward, up, -right),
+            CubeFace::Up => (right, -forward, up),
+            CubeFace::Down => (right, forward, -up),
+        };
+        CameraBasis { right, up, forward }
+    }
+}
+
+/// Rectangle of the frame rendered as one image: the whole frame, or one
+/// eye of a stereo pair, or one face of a cubemap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct View {
+    pub left: u32,
+    pub top: u32,
+    pub width: u32,
+    pub height: u32,
+    pub eye: Option<Eye>,
+    pub face: Option<CubeFace>,
+}
+
+impl View {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.left as f64
+            && x < (self.left + self.width) as f64
+            && y >= self.top as f64
+            && y < (self.top + self.height) as f64
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Camera {
+    pub position: Point,
+    pub look_at: Point,
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub up: Vector3,
+    /// Rotation around the viewing direction, in degrees.
+    pub roll: f64,
+    /// Overrides the scene's `fov` when present.
+    pub fov: Option<f64>,
+    pub projection: Projection,
+    pub stereo: Option<Stereo>,
+    /// Diameter of the lens. The default of 0 is a pinhole camera, which has
+    /// everything in focus.
+    pub aperture: f64,
+    /// Distance to the plane in focus; defaults to the distance to
+    /// `look_at`.
+    pub focus_distance: Option<f64>,
+    /// Number of aperture blades, which give out-of-focus highlights their
+    /// polygonal shape. Fewer than 3 makes the aperture round.
+    pub blades: u32,
+    /// Rotation of the aperture blades, in degrees.
+    pub blade_rotation: f64,
+    /// Times at which the shutter opens and closes, in the units of element
+    /// motions. Samples are spread across the interval, which blurs moving
+    /// elements; by default it's instantaneous.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+impl Default for Camera {
+    /// Eye at the origin looking down -Z, which is what scenes assumed
+    /// before cameras could be positioned.
+    fn default() -> Self {
+        Self {
+            position: Point::zero(),
+            look_at: Point {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            up: Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            roll: 0.0,
+            fov: None,
+            projection: Projection::Perspective,
+            stereo: None,
+            aperture: 0.0,
+            focus_distance: None,
+            blades: 0,
+            blade_rotation: 0.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn basis(&self) -> CameraBasis {
+        let to_target = self.look_at - self.position;
+        let forward = if to_target.length() < 1e-9 {
+            // Looking at the camera's own position; keep the default view
+            // down -z rather than dividing by zero.
+            Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            }
+        } else {
+            to_target.normalize()
+        };
+        let mut right = forward.cross(&self.up);
+        if right.length() < 1e-9 {
+            // Looking straight along `up`; any perpendicular will do.
+            right = forward.cross(&Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            });
+            if right.length() < 1e-9 {
+                right = forward.cross(&Vector3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 1.0,
+                });
+            }
+        }
+        let right = right.normalize();
+        let up = right.cross(&forward);
+
+        let (sin, cos) = self.roll.to_radians().sin_cos();
+        CameraBasis {
+            right: right * cos + up * sin,
+            up: up * cos - right * sin,
+            forward,
+        }
+    }
+
+    /// The images a `width` by `height` frame is split into, eyes first
+    /// and then cubemap faces.
+    pub fn views(&self, width: u32, height: u32) -> Vec<View> {
+        let whole = View {
+            left: 0,
+            top: 0,
+            width,
+            height,
+            eye: None,
+            face: None,
+        };
+        let eyes = match self.stereo.as_ref().map(|s| s.layout) {
+            None => vec![whole],
+            Some(StereoLayout::SideBySide) => vec![
+                View {
+                    width: width / 2,
+                    eye: Some(Eye::Left),
+                    ..whole
+                },
+                View {
+                    left: width / 2,
+                    width: width / 2,
+                    eye: Some(Eye::Right),
+                    ..whole
+                },
+            ],
+            Some(StereoLayout::TopBottom) => vec![
+                View {
+                    height: height / 2,
+                    eye: Some(Eye::Left),
+                    ..whole
+                },
+                View {
+                    top: height / 2,
+                    height: height / 2,
+                    eye: Some(Eye::Right),
+                    ..whole
+                },
+            ],
+        };
+        if self.projection != Projection::Cubemap {
+            return eyes;
+        }
+
+        eyes.iter()
+            .flat_map(|eye| {
+                let side = (eye.width / 4).min(eye.height / 3);
+                CubeFace::ALL.iter().map(move |&face| {
+                    let (column, row) = face.cross_cell();
+                    View {
+                        left: eye.left + column * side,
+                        top: eye.top + row * side,
+                        width: side,
+                        height: side,
+                        face: Some(face),
+                        ..*eye
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Fails for settings that can't be rendered together. Panoramas look
+    /// all around, so there's no single direction to separate the eyes
+    /// across.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.stereo.is_some() && self.projection == Projection::Equirectangular {
+            return Err("Stereo cameras can't use the Equirectangular projection".to_string());
+        }
+        Ok(())
+    }
+
+    /// Offset of `eye` along the basis' `right`.
+    pub fn eye_offset(&self, eye: Eye) -> f64 {
+        let half = self
+            .stereo
+            .as_ref()
+            .map_or(0.0, |s| s.interocular_distance / 2.0);
+        match eye {
+            Eye::Left => -half,
+            Eye::Right => half,
+        }
+    }
+
+    pub fn convergence(&self) -> f64 {
+        self.stereo
+            .as_ref()
+            .and_then(|s| s.convergence)
+            .unwrap_or_else(|| self.focus_distance())
+    }
+
+    pub fn focus_distance(&self) -> f64 {
+        self.focus_distance
+            .unwrap_or_else(|| (self.look_at - self.position).length())
+    }
+
+    /// Picks `count` times while the shutter is open, one in each of as
+    /// many equal parts of the interval, in random order so they don't line
+    /// up with the pixel samples. An instantaneous shutter always returns
+    /// the opening time without using `rng`.
+    pub fn sample_times<R: Rng>(&self, count: u32, rng: &mut R) -> Vec<f64> {
+        let span = self.shutter_close - self.shutter_open;
+        if span <= 0.0 {
+            return vec![self.shutter_open; count as usize];
+        }
+        let mut times: Vec<f64> = (0..count)
+            .map(|i| self.shutter_open + (i as f64 + rng.gen::<f64>()) / count as f64 * span)
+            .collect();
+        times.shuffle(rng);
+        times
+    }
+
+    /// Picks a point on the lens, as offsets along the basis' `right` and
+    /// `up`. Pinhole cameras always return the center without using `rng`.
+    pub fn sample_lens<R: Rng>(&self, rng: &mut R) -> (f64, f64) {
+        if self.aperture <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let radius = self.aperture / 2.0;
+        let (u, v): (f64, f64) = (rng.gen(), rng.gen());
+        if self.blades < 3 {
+            let (x, y) = concentric_disk(u, v);
+            return (x * radius, y * radius);
+        }
+
+        // Uniform over one of the triangles the polygon is split into.
+        let blades = self.blades as f64;
+        let sector = (u * blades).floor().min(blades - 1.0);
+        let (a, b) = ((u * blades - sector).sqrt(), v);
+        let corner = |i: f64| {
+            let angle = self.blade_rotation.to_radians() + 2.0 * PI * i / blades;
+            (angle.cos(), angle.sin())
+        };
+        let (first, second) = (corner(sector), corner(sector + 1.0));
+        (
+            a * ((1.0 - b) * first.0 + b * second.0) * radius,
+            a * ((1.0 - b) * first.1 + b * second.1) * radius,
+        )
+    }
+}
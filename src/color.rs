@@ -2,6 +2,8 @@ use std::ops::{Add, Mul};
 
 use image::{Pixel, Rgb, Rgba};
 
+use crate::tonemap::ToneMapping;
+
 const GAMMA: f32 = 2.2;
 
 fn gamma_encode(linear: f32) -> f32 {
@@ -18,29 +20,53 @@ pub struct Color {
     pub green: f32,
     pub blue: f32,
 }
+
+pub const BLACK: Color = Color {
+    red: 0.0,
+    green: 0.0,
+    blue: 0.0,
+};
+
+pub const WHITE: Color = Color {
+    red: 1.0,
+    green: 1.0,
+    blue: 1.0,
+};
+
 impl Color {
+    pub fn max_component(&self) -> f32 {
+        self.red.max(self.green).max(self.blue)
+    }
+
+    /// Relative luminance, using the Rec. 709 primaries.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
     pub fn clamp(&self) -> Self {
         Self {
-            red: self.red.min(1.0).max(0.0),
-            green: self.green.min(1.0).max(0.0),
-            blue: self.blue.min(1.0).max(0.0),
+            red: self.red.clamp(0.0, 1.0),
+            green: self.green.clamp(0.0, 1.0),
+            blue: self.blue.clamp(0.0, 1.0),
         }
     }
 
-    pub fn to_rgba(&self) -> Rgba<u8> {
+    pub fn to_rgba(&self, tone_mapping: &ToneMapping) -> Rgba<u8> {
+        let color = tone_mapping.apply(*self);
         Rgba::from_channels(
-            (gamma_encode(self.red) * 255.0) as u8,
-            (gamma_encode(self.green) * 255.0) as u8,
-            (gamma_encode(self.blue) * 255.0) as u8,
+            (gamma_encode(color.red) * 255.0) as u8,
+            (gamma_encode(color.green) * 255.0) as u8,
+            (gamma_encode(color.blue) * 255.0) as u8,
             255,
         )
     }
 
-    pub fn to_rgb(&self) -> Rgb<u8> {
+    pub fn to_rgb(&self, tone_mapping: &ToneMapping) -> Rgb<u8> {
+        let color = tone_mapping.apply(*self);
         Rgb::from_channels(
-            (gamma_encode(self.red) * 255.0) as u8,
-            (gamma_encode(self.green) * 255.0) as u8,
-            (gamma_encode(self.blue) * 255.0) as u8,
+            (gamma_encode(color.red) * 255.0) as u8,
+            (gamma_encode(color.green) * 255.0) as u8,
+            (gamma_encode(color.blue) * 255.0) as u8,
             0 // ignored
         )
     }
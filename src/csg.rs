@@ -0,0 +1,497 @@
+use crate::{
+    element::{
+        AxisAlignedBox, Cone, Csg, CsgOperation, Cylinder, Disk, Element, Plane, Sphere, Torus,
+    },
+    heightfield::Heightfield,
+    mesh::{Mesh, TriangleHit},
+    point::Point,
+    rendering::{slabs, triangle_crossing, HitDetail, Intersectable, Ray, TextureCoords},
+    sdf::Sdf,
+    vector::Vector3,
+};
+
+/// Part of the line through a ray that lies inside a solid, by distance
+/// along the ray.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interval {
+    pub enter: f64,
+    pub exit: f64,
+    /// Which part of the surface the line crosses at `enter` and `exit`.
+    pub enter_detail: HitDetail,
+    pub exit_detail: HitDetail,
+}
+
+impl Interval {
+    fn new(enter: f64, exit: f64) -> Self {
+        Interval {
+            enter,
+            exit,
+            enter_detail: HitDetail::default(),
+            exit_detail: HitDetail::default(),
+        }
+    }
+}
+
+/// Shapes that can take part in constructive solid geometry.
+pub trait Solid: Intersectable {
+    /// Sorted, disjoint intervals of the whole line through `ray`, including
+    /// behind its origin, that lie inside the shape. Open surfaces report an
+    /// empty interval wherever the line crosses them.
+    fn intervals(&self, ray: &Ray) -> Vec<Interval>;
+}
+
+impl Solid for Element {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        match self.transform().map(|t| &t.affine) {
+            Some(t) => {
+                let direction = t.vector_to_object(&ray.direction);
+                let scale = direction.length();
+                let local_ray = Ray {
+                    origin: t.point_to_object(&ray.origin),
+                    direction: direction * scale.recip(),
+                    time: ray.time,
+                };
+                self.object_intervals(&local_ray)
+                    .into_iter()
+                    .map(|i| Interval {
+                        enter: i.enter / scale,
+                        exit: i.exit / scale,
+                        ..i
+                    })
+                    .collect()
+            }
+            None => self.object_intervals(ray),
+        }
+    }
+}
+
+impl Element {
+    fn object_intervals(&self, ray: &Ray) -> Vec<Interval> {
+        match *self {
+            Element::Sphere(ref s) => s.intervals(ray),
+            Element::Plane(ref p) => p.intervals(ray),
+            Element::Mesh(ref m) => m.intervals(ray),
+            Element::Instance(ref i) => i.geometry().intervals(ray),
+            Element::Box(ref b) => b.intervals(ray),
+            Element::Cylinder(ref c) => c.intervals(ray),
+            Element::Cone(ref c) => c.intervals(ray),
+            Element::Disk(ref d) => d.intervals(ray),
+            Element::Torus(ref t) => t.intervals(ray),
+            Element::Csg(ref c) => c.intervals(ray),
+            Element::Sdf(ref s) => s.intervals(ray),
+            Element::Heightfield(ref h) => h.intervals(ray),
+        }
+    }
+}
+
+impl Intersectable for Csg {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.hit(ray).map(|(distance, _)| distance)
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<(f64, HitDetail)> {
+        self.intervals(ray)
+            .iter()
+            .flat_map(|i| [(i.enter, i.enter_detail), (i.exit, i.exit_detail)])
+            .find(|&(t, _)| t > 0.0)
+    }
+
+    fn surface_normal(&self, hit_point: &Point, detail: &HitDetail) -> Vector3 {
+        let (child, child_detail) = self.child(detail);
+        let normal = child.surface_normal(hit_point, &child_detail);
+        if detail.flipped {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point, detail: &HitDetail) -> TextureCoords {
+        let (child, child_detail) = self.child(detail);
+        child.texture_coords(hit_point, &child_detail)
+    }
+}
+
+/// Hits on the right child are numbered after the leaves of the left one,
+/// and flipped by a difference, so each interval bound remembers the leaf it
+/// came from.
+impl Solid for Csg {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let offset = self.left.parts();
+        let flip = self.op == CsgOperation::Difference;
+        let from_right = |detail: HitDetail| HitDetail {
+            part: detail.part + offset,
+            flipped: detail.flipped != flip,
+            ..detail
+        };
+        let right: Vec<Interval> = self
+            .right
+            .intervals(ray)
+            .into_iter()
+            .map(|i| Interval {
+                enter_detail: from_right(i.enter_detail),
+                exit_detail: from_right(i.exit_detail),
+                ..i
+            })
+            .collect();
+        combine(self.op, &self.left.intervals(ray), &right)
+    }
+}
+
+impl Csg {
+    /// The child holding the leaf `detail` is on, and the hit as that child
+    /// sees it. The flip is left to the outermost CSG element, which has the
+    /// whole tree's worth of it in `detail`.
+    fn child(&self, detail: &HitDetail) -> (&Element, HitDetail) {
+        let left_parts = self.left.parts();
+        let (child, part) = if detail.part < left_parts {
+            (&self.left, detail.part)
+        } else {
+            (&self.right, detail.part - left_parts)
+        };
+        (
+            child,
+            HitDetail {
+                part,
+                flipped: false,
+                ..*detail
+            },
+        )
+    }
+}
+
+impl Element {
+    /// Leaves of the CSG tree below the element; anything else counts as a
+    /// single one.
+    fn parts(&self) -> usize {
+        match *self {
+            Element::Csg(ref c) => c.left.parts() + c.right.parts(),
+            Element::Instance(ref i) => i.geometry().parts(),
+            _ => 1,
+        }
+    }
+}
+
+/// Applies `op` to two sets of sorted, disjoint intervals.
+fn combine(op: CsgOperation, left: &[Interval], right: &[Interval]) -> Vec<Interval> {
+    // Entries sort before exits at the same distance, so empty intervals
+    // still register.
+    let mut events: Vec<(f64, bool, bool, HitDetail)> = Vec::new();
+    for (is_left, intervals) in [(true, left), (false, right)].iter() {
+        for i in intervals.iter() {
+            events.push((i.enter, *is_left, true, i.enter_detail));
+            events.push((i.exit, *is_left, false, i.exit_detail));
+        }
+    }
+    events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap().then(b.2.cmp(&a.2)));
+
+    let inside = |in_left: bool, in_right: bool| match op {
+        CsgOperation::Union => in_left || in_right,
+        CsgOperation::Intersection => in_left && in_right,
+        CsgOperation::Difference => in_left && !in_right,
+    };
+
+    let mut result: Vec<Interval> = Vec::new();
+    let (mut in_left, mut in_right) = (false, false);
+    let (mut enter, mut enter_detail) = (0.0, HitDetail::default());
+    for (t, is_left, entering, detail) in events {
+        let was_inside = inside(in_left, in_right);
+        if is_left {
+            in_left = entering;
+        } else {
+            in_right = entering;
+        }
+        match (was_inside, inside(in_left, in_right)) {
+            (false, true) => {
+                enter = t;
+                enter_detail = detail;
+            }
+            (true, false) => match result.last_mut() {
+                // Touching intervals would leave a surface inside the solid.
+                Some(last) if last.exit >= enter => {
+                    last.exit = t;
+                    last.exit_detail = detail;
+                }
+                _ => result.push(Interval {
+                    enter,
+                    exit: t,
+                    enter_detail,
+                    exit_detail: detail,
+                }),
+            },
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Pairs up sorted crossings of a closed surface into the intervals between
+/// them.
+fn pair_crossings(mut crossings: Vec<f64>) -> Vec<Interval> {
+    crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    crossings
+        .chunks_exact(2)
+        .map(|pair| Interval::new(pair[0], pair[1]))
+        .collect()
+}
+
+/// Empty intervals at each crossing of an open surface.
+fn thin_crossings(mut crossings: Vec<f64>) -> Vec<Interval> {
+    crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    crossings.into_iter().map(|t| Interval::new(t, t)).collect()
+}
+
+/// Where `a t² + b t + c` is not positive.
+fn quadratic_below_zero(a: f64, b: f64, c: f64) -> Vec<Interval> {
+    let everywhere = Interval::new(f64::NEG_INFINITY, f64::INFINITY);
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return if c <= 0.0 {
+                vec![everywhere]
+            } else {
+                Vec::new()
+            };
+        }
+        let t = -c / b;
+        return vec![if b > 0.0 {
+            Interval::new(f64::NEG_INFINITY, t)
+        } else {
+            Interval::new(t, f64::INFINITY)
+        }];
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return if a < 0.0 {
+            vec![everywhere]
+        } else {
+            Vec::new()
+        };
+    }
+    let sqrt = discriminant.sqrt();
+    let (mut t0, mut t1) = ((-b - sqrt) / (2.0 * a), (-b + sqrt) / (2.0 * a));
+    if t0 > t1 {
+        std::mem::swap(&mut t0, &mut t1);
+    }
+    if a > 0.0 {
+        vec![Interval::new(t0, t1)]
+    } else {
+        vec![
+            Interval::new(f64::NEG_INFINITY, t0),
+            Interval::new(t1, f64::INFINITY),
+        ]
+    }
+}
+
+/// Where the line through `ray` lies between the heights `bottom` and `top`.
+fn height_range(ray: &Ray, bottom: f64, top: f64) -> Vec<Interval> {
+    if ray.direction.y.abs() < 1e-12 {
+        return if (bottom..=top).contains(&ray.origin.y) {
+            vec![Interval::new(f64::NEG_INFINITY, f64::INFINITY)]
+        } else {
+            Vec::new()
+        };
+    }
+    let mut t0 = (bottom - ray.origin.y) / ray.direction.y;
+    let mut t1 = (top - ray.origin.y) / ray.direction.y;
+    if t0 > t1 {
+        std::mem::swap(&mut t0, &mut t1);
+    }
+    vec![Interval::new(t0, t1)]
+}
+
+/// Crossings of the curved side of a shape for which `inside` holds, given
+/// by `quadratic_below_zero`.
+fn side_crossings(inside: &[Interval], bottom: f64, top: f64, ray: &Ray) -> Vec<f64> {
+    inside
+        .iter()
+        .flat_map(|i| vec![i.enter, i.exit])
+        .filter(|t| t.is_finite())
+        .filter(|&t| (bottom..=top).contains(&(ray.origin.y + ray.direction.y * t)))
+        .collect()
+}
+
+impl Solid for Sphere {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let l = self.center - ray.origin;
+        let adj = l.dot(&ray.direction);
+        let d2 = l.dot(&l) - (adj * adj);
+        let radius2 = self.radius * self.radius;
+        if d2 > radius2 {
+            return Vec::new();
+        }
+        let thc = (radius2 - d2).sqrt();
+        vec![Interval::new(adj - thc, adj + thc)]
+    }
+}
+
+/// Planes bound the half-space behind their visible side.
+impl Solid for Plane {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let denom = self.normal.dot(&ray.direction);
+        let height = (ray.origin - self.origin).dot(&self.normal);
+        quadratic_below_zero(0.0, -denom, -height)
+    }
+}
+
+/// Meshes are assumed to be closed; crossings are paired up in order.
+impl Solid for Mesh {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let bounds = match self.bvh.bounds() {
+            Some(bounds) => bounds,
+            None => return Vec::new(),
+        };
+        let near = match slabs(&bounds.min, &bounds.max, ray) {
+            Some((near, _)) => near,
+            None => return Vec::new(),
+        };
+        // Starting in front of the mesh lets the hierarchy, which only looks
+        // ahead of the ray, find every crossing. A whole diagonal of the
+        // bounds is well clear of them, whatever the mesh's scale.
+        let start = near - (bounds.max - bounds.min).length();
+        let shifted = Ray {
+            origin: ray.origin + ray.direction * start,
+            direction: ray.direction,
+            time: ray.time,
+        };
+        let mut crossings = Vec::new();
+        self.bvh.intersecting(&shifted, |i| {
+            if let Some((t, u, v)) = triangle_crossing(self.vertices(&self.triangles[i]), ray) {
+                let detail = HitDetail {
+                    triangle: Some(TriangleHit { triangle: i, u, v }),
+                    ..HitDetail::default()
+                };
+                crossings.push((t, detail));
+            }
+        });
+        crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        crossings
+            .chunks_exact(2)
+            .map(|pair| Interval {
+                enter: pair[0].0,
+                exit: pair[1].0,
+                enter_detail: pair[0].1,
+                exit_detail: pair[1].1,
+            })
+            .collect()
+    }
+}
+
+/// Heightfields have no sides or bottom, so they act as open surfaces.
+impl Solid for Heightfield {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        thin_crossings(self.crossings(ray, false))
+    }
+}
+
+impl Solid for AxisAlignedBox {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        match slabs(&self.min, &self.max, ray) {
+            Some((enter, exit)) => vec![Interval::new(enter, exit)],
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Solid for Cylinder {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let local = Ray {
+            origin: Point {
+                x: ray.origin.x - self.center.x,
+                y: ray.origin.y - self.center.y,
+                z: ray.origin.z - self.center.z,
+            },
+            direction: ray.direction,
+            time: ray.time,
+        };
+        let (o, d) = (local.origin, local.direction);
+        let infinite = quadratic_below_zero(
+            d.x * d.x + d.z * d.z,
+            2.0 * (o.x * d.x + o.z * d.z),
+            o.x * o.x + o.z * o.z - self.radius * self.radius,
+        );
+        if self.capped {
+            combine(
+                CsgOperation::Intersection,
+                &infinite,
+                &height_range(&local, 0.0, self.height),
+            )
+        } else {
+            thin_crossings(side_crossings(&infinite, 0.0, self.height, &local))
+        }
+    }
+}
+
+impl Solid for Cone {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let local = Ray {
+            origin: Point {
+                x: ray.origin.x - self.center.x,
+                y: ray.origin.y - self.center.y,
+                z: ray.origin.z - self.center.z,
+            },
+            direction: ray.direction,
+            time: ray.time,
+        };
+        let (o, d) = (local.origin, local.direction);
+        let k2 = (self.radius / self.height).powi(2);
+        let h = self.height - o.y;
+        // Inside both nappes of the infinite cone; the height range keeps
+        // only the lower one.
+        let infinite = quadratic_below_zero(
+            d.x * d.x + d.z * d.z - k2 * d.y * d.y,
+            2.0 * (o.x * d.x + o.z * d.z + k2 * h * d.y),
+            o.x * o.x + o.z * o.z - k2 * h * h,
+        );
+        if self.capped {
+            combine(
+                CsgOperation::Intersection,
+                &infinite,
+                &height_range(&local, 0.0, self.height),
+            )
+        } else {
+            thin_crossings(side_crossings(&infinite, 0.0, self.height, &local))
+        }
+    }
+}
+
+impl Solid for Disk {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let denom = self.normal.dot(&ray.direction);
+        if denom.abs() < 1e-12 {
+            return Vec::new();
+        }
+        let t = (self.center - ray.origin).dot(&self.normal) / denom;
+        let offset = (ray.origin + ray.direction * t) - self.center;
+        if offset.norm() <= self.radius * self.radius {
+            vec![Interval::new(t, t)]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Solid for Torus {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        pair_crossings(self.crossings(ray))
+    }
+}
+
+impl Solid for Sdf {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let (start, end) = match self.range(ray) {
+            Some(range) => range,
+            None => return Vec::new(),
+        };
+        let mut crossings = self.march(ray, start, end, false);
+        // Lines starting or ending inside unbounded shapes are cut off at
+        // the end of the range.
+        if self.distance(&(ray.origin + ray.direction * start)) < self.epsilon {
+            crossings.insert(0, start);
+        }
+        if crossings.len() % 2 == 1 {
+            crossings.push(end);
+        }
+        pair_crossings(crossings)
+    }
+}
@@ -1,15 +1,32 @@
+use std::{borrow::Cow, f64::consts::PI, sync::Arc};
+
 use crate::{
     color::Color,
+    heightfield::{load_heightfield, Heightfield},
     material::Material,
+    mesh::{load_mesh, Mesh},
+    motion::Motion,
     point::Point,
-    rendering::{Intersectable, TextureCoords},
-    vector::Vector3,
+    rendering::{HitDetail, TextureCoords},
+    sampling::{concentric_disk, tangent_frame, uniform_sphere},
+    sdf::Sdf,
+    vector::{Affine, Transform, Vector3},
 };
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Element {
     Sphere(Sphere),
     Plane(Plane),
+    Mesh(#[serde(deserialize_with = "load_mesh")] Mesh),
+    Instance(Instance),
+    Box(AxisAlignedBox),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Disk(Disk),
+    Torus(Torus),
+    Csg(Csg),
+    Sdf(Sdf),
+    Heightfield(#[serde(deserialize_with = "load_heightfield")] Heightfield),
 }
 
 impl Element {
@@ -17,13 +34,104 @@ impl Element {
         match *self {
             Element::Sphere(ref s) => s.material.coloration.color(coords),
             Element::Plane(ref p) => p.material.coloration.color(coords),
+            Element::Mesh(ref m) => m.material.coloration.color(coords),
+            Element::Instance(ref i) => i.material().coloration.color(coords),
+            Element::Box(ref b) => b.material.coloration.color(coords),
+            Element::Cylinder(ref c) => c.material.coloration.color(coords),
+            Element::Cone(ref c) => c.material.coloration.color(coords),
+            Element::Disk(ref d) => d.material.coloration.color(coords),
+            Element::Torus(ref t) => t.material.coloration.color(coords),
+            Element::Csg(ref c) => c.material().coloration.color(coords),
+            Element::Sdf(ref s) => s.material.coloration.color(coords),
+            Element::Heightfield(ref h) => h.material.coloration.color(coords),
+        }
+    }
+
+    /// Normal at `hit_point`, with the element where it is at `time`.
+    pub fn surface_normal_at(&self, hit_point: &Point, detail: &HitDetail, time: f64) -> Vector3 {
+        self.placed_normal(self.transform_at(time).as_deref(), hit_point, detail)
+    }
+
+    /// Texture coordinates at `hit_point`, with the element where it is at
+    /// `time`.
+    pub fn texture_coords_at(
+        &self,
+        hit_point: &Point,
+        detail: &HitDetail,
+        time: f64,
+    ) -> TextureCoords {
+        self.placed_texture_coords(self.transform_at(time).as_deref(), hit_point, detail)
+    }
+
+    /// Normal at `hit_point` with the element placed by `transform`.
+    pub(crate) fn placed_normal(
+        &self,
+        transform: Option<&Affine>,
+        hit_point: &Point,
+        detail: &HitDetail,
+    ) -> Vector3 {
+        match transform {
+            Some(t) => {
+                let normal = self.object_normal(&t.point_to_object(hit_point), detail);
+                t.normal_to_world(&normal).normalize()
+            }
+            None => self.object_normal(hit_point, detail),
+        }
+    }
+
+    pub(crate) fn placed_texture_coords(
+        &self,
+        transform: Option<&Affine>,
+        hit_point: &Point,
+        detail: &HitDetail,
+    ) -> TextureCoords {
+        match transform {
+            Some(t) => self.object_texture_coords(&t.point_to_object(hit_point), detail),
+            None => self.object_texture_coords(hit_point, detail),
+        }
+    }
+
+    /// Object-to-world transform, if the element has one.
+    pub fn transform(&self) -> Option<&Transform> {
+        match *self {
+            Element::Sphere(ref s) => s.transform.as_ref(),
+            Element::Plane(ref p) => p.transform.as_ref(),
+            Element::Mesh(ref m) => m.transform.as_ref(),
+            Element::Instance(ref i) => i.transform.as_ref(),
+            Element::Box(ref b) => b.transform.as_ref(),
+            Element::Cylinder(ref c) => c.transform.as_ref(),
+            Element::Cone(ref c) => c.transform.as_ref(),
+            Element::Disk(ref d) => d.transform.as_ref(),
+            Element::Torus(ref t) => t.transform.as_ref(),
+            Element::Csg(ref c) => c.transform.as_ref(),
+            Element::Sdf(ref s) => s.transform.as_ref(),
+            Element::Heightfield(ref h) => h.transform.as_ref(),
         }
     }
 
-    pub fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+    pub fn motion(&self) -> Option<&Motion> {
         match *self {
-            Element::Sphere(ref s) => s.surface_normal(hit_point),
-            Element::Plane(ref p) => p.surface_normal(hit_point),
+            Element::Sphere(ref s) => s.motion.as_ref(),
+            Element::Plane(ref p) => p.motion.as_ref(),
+            Element::Mesh(ref m) => m.motion.as_ref(),
+            Element::Instance(ref i) => i.motion.as_ref(),
+            Element::Box(ref b) => b.motion.as_ref(),
+            Element::Cylinder(ref c) => c.motion.as_ref(),
+            Element::Cone(ref c) => c.motion.as_ref(),
+            Element::Disk(ref d) => d.motion.as_ref(),
+            Element::Torus(ref t) => t.motion.as_ref(),
+            Element::Csg(ref c) => c.motion.as_ref(),
+            Element::Sdf(ref s) => s.motion.as_ref(),
+            Element::Heightfield(ref h) => h.motion.as_ref(),
+        }
+    }
+
+    /// Object-to-world transform at `time`, including the element's motion.
+    pub fn transform_at(&self, time: f64) -> Option<Cow<'_, Affine>> {
+        let own = self.transform().map(|t| &t.affine);
+        match self.motion() {
+            Some(motion) => Some(Cow::Owned(motion.transform_at(own, time))),
+            None => own.map(Cow::Borrowed),
         }
     }
 
@@ -31,6 +139,114 @@ impl Element {
         match *self {
             Element::Sphere(ref s) => s.material.albedo,
             Element::Plane(ref p) => p.material.albedo,
+            Element::Mesh(ref m) => m.material.albedo,
+            Element::Instance(ref i) => i.material().albedo,
+            Element::Box(ref b) => b.material.albedo,
+            Element::Cylinder(ref c) => c.material.albedo,
+            Element::Cone(ref c) => c.material.albedo,
+            Element::Disk(ref d) => d.material.albedo,
+            Element::Torus(ref t) => t.material.albedo,
+            Element::Csg(ref c) => c.material().albedo,
+            Element::Sdf(ref s) => s.material.albedo,
+            Element::Heightfield(ref h) => h.material.albedo,
+        }
+    }
+
+    /// Total area before any transform, or `None` for elements that can't be
+    /// sampled by `sample_surface`.
+    pub fn surface_area(&self) -> Option<f64> {
+        match *self {
+            Element::Sphere(ref s) => Some(4.0 * PI * s.radius * s.radius),
+            Element::Box(ref b) => Some(b.surface_area()),
+            Element::Disk(ref d) => Some(PI * d.radius * d.radius),
+            Element::Plane(_)
+            | Element::Cylinder(_)
+            | Element::Cone(_)
+            | Element::Torus(_)
+            | Element::Csg(_)
+            | Element::Sdf(_)
+            | Element::Heightfield(_) => None,
+            Element::Mesh(ref m) => Some(m.surface_area()),
+            Element::Instance(ref i) => i.geometry().surface_area(),
+        }
+    }
+
+    /// Picks a point on the surface for (`u`, `v`) in [0, 1)², so emissive
+    /// elements can be sampled like lights.
+    pub fn sample_surface(&self, u: f64, v: f64, time: f64) -> Option<SurfaceSample> {
+        let sample = self.sample_object_surface(u, v)?;
+        Some(match self.transform_at(time) {
+            Some(t) => SurfaceSample {
+                point: t.point_to_world(&sample.point),
+                normal: t.normal_to_world(&sample.normal).normalize(),
+                pdf: sample.pdf / t.area_scale(&sample.normal),
+                detail: sample.detail,
+            },
+            None => sample,
+        })
+    }
+
+    /// Density per unit area with which `sample_surface` picks `point`,
+    /// found on the element with `detail`.
+    pub fn surface_pdf(&self, point: &Point, detail: &HitDetail, time: f64) -> f64 {
+        match self.transform_at(time) {
+            Some(t) => {
+                let local = t.point_to_object(point);
+                let normal = self.object_normal(&local, detail);
+                self.object_surface_pdf(&local, detail) / t.area_scale(&normal)
+            }
+            None => self.object_surface_pdf(point, detail),
+        }
+    }
+
+    fn sample_object_surface(&self, u: f64, v: f64) -> Option<SurfaceSample> {
+        match *self {
+            Element::Sphere(ref s) => {
+                let normal = uniform_sphere(u, v);
+                Some(SurfaceSample {
+                    point: s.center + normal * s.radius,
+                    normal,
+                    pdf: 1.0 / (4.0 * PI * s.radius * s.radius),
+                    detail: HitDetail::default(),
+                })
+            }
+            Element::Box(ref b) => b.sample_surface(u, v),
+            Element::Disk(ref d) => {
+                let (x, y) = concentric_disk(u, v);
+                let (tangent, bitangent) = tangent_frame(&d.normal);
+                Some(SurfaceSample {
+                    point: d.center + (tangent * x + bitangent * y) * d.radius,
+                    normal: d.normal,
+                    pdf: 1.0 / (PI * d.radius * d.radius),
+                    detail: HitDetail::default(),
+                })
+            }
+            Element::Plane(_)
+            | Element::Cylinder(_)
+            | Element::Cone(_)
+            | Element::Torus(_)
+            | Element::Csg(_)
+            | Element::Sdf(_)
+            | Element::Heightfield(_) => None,
+            Element::Mesh(ref m) => m.sample_surface(u, v),
+            Element::Instance(ref i) => i.geometry().sample_surface(u, v, 0.0),
+        }
+    }
+
+    fn object_surface_pdf(&self, point: &Point, detail: &HitDetail) -> f64 {
+        match *self {
+            Element::Sphere(ref s) => 1.0 / (4.0 * PI * s.radius * s.radius),
+            Element::Box(ref b) => 1.0 / b.surface_area(),
+            Element::Disk(ref d) => 1.0 / (PI * d.radius * d.radius),
+            Element::Plane(_)
+            | Element::Cylinder(_)
+            | Element::Cone(_)
+            | Element::Torus(_)
+            | Element::Csg(_)
+            | Element::Sdf(_)
+            | Element::Heightfield(_) => 0.0,
+            Element::Mesh(ref m) => detail.triangle.map_or(0.0, |hit| m.surface_pdf(&hit)),
+            Element::Instance(ref i) => i.geometry().surface_pdf(point, detail, 0.0),
         }
     }
 
@@ -38,6 +254,52 @@ impl Element {
         match *self {
             Element::Sphere(ref s) => &s.material,
             Element::Plane(ref p) => &p.material,
+            Element::Mesh(ref m) => &m.material,
+            Element::Instance(ref i) => i.material(),
+            Element::Box(ref b) => &b.material,
+            Element::Cylinder(ref c) => &c.material,
+            Element::Cone(ref c) => &c.material,
+            Element::Disk(ref d) => &d.material,
+            Element::Torus(ref t) => &t.material,
+            Element::Csg(ref c) => c.material(),
+            Element::Sdf(ref s) => &s.material,
+            Element::Heightfield(ref h) => &h.material,
+        }
+    }
+
+    /// The element's own material, which instances and CSG elements only
+    /// have when they override the one they'd otherwise use.
+    pub fn material_mut(&mut self) -> Option<&mut Material> {
+        match *self {
+            Element::Sphere(ref mut s) => Some(&mut s.material),
+            Element::Plane(ref mut p) => Some(&mut p.material),
+            Element::Mesh(ref mut m) => Some(&mut m.material),
+            Element::Instance(ref mut i) => i.material_override.as_mut(),
+            Element::Box(ref mut b) => Some(&mut b.material),
+            Element::Cylinder(ref mut c) => Some(&mut c.material),
+            Element::Cone(ref mut c) => Some(&mut c.material),
+            Element::Disk(ref mut d) => Some(&mut d.material),
+            Element::Torus(ref mut t) => Some(&mut t.material),
+            Element::Csg(ref mut c) => c.material.as_mut(),
+            Element::Sdf(ref mut s) => Some(&mut s.material),
+            Element::Heightfield(ref mut h) => Some(&mut h.material),
+        }
+    }
+
+    pub fn transform_mut(&mut self) -> &mut Option<Transform> {
+        match *self {
+            Element::Sphere(ref mut s) => &mut s.transform,
+            Element::Plane(ref mut p) => &mut p.transform,
+            Element::Mesh(ref mut m) => &mut m.transform,
+            Element::Instance(ref mut i) => &mut i.transform,
+            Element::Box(ref mut b) => &mut b.transform,
+            Element::Cylinder(ref mut c) => &mut c.transform,
+            Element::Cone(ref mut c) => &mut c.transform,
+            Element::Disk(ref mut d) => &mut d.transform,
+            Element::Torus(ref mut t) => &mut t.transform,
+            Element::Csg(ref mut c) => &mut c.transform,
+            Element::Sdf(ref mut s) => &mut s.transform,
+            Element::Heightfield(ref mut h) => &mut h.transform,
         }
     }
 }
@@ -47,6 +309,10 @@ pub struct Sphere {
     pub center: Point,
     pub radius: f64,
     pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -55,15 +321,258 @@ pub struct Plane {
     #[serde(deserialize_with = "Vector3::deserialize_normalized")]
     pub normal: Vector3,
     pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+}
+
+/// A point on the surface of an element.
+pub struct SurfaceSample {
+    pub point: Point,
+    pub normal: Vector3,
+    /// Probability density per unit area.
+    pub pdf: f64,
+    /// Where the point is on the element, as if a ray had hit it there.
+    pub detail: HitDetail,
+}
+
+/// Placement of a shared geometry from `Scene::geometries`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Instance {
+    /// Name of the geometry in `Scene::geometries`.
+    pub geometry: String,
+    /// Applied on top of the geometry's own transform.
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+    /// Replaces the geometry's material for this instance only.
+    #[serde(default)]
+    pub material_override: Option<Material>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub resolved: Option<Arc<Element>>,
+}
+
+impl Instance {
+    /// The shared geometry; only available once `Scene::resolve_instances`
+    /// has run, as it does before rendering.
+    pub fn geometry(&self) -> &Element {
+        self.resolved
+            .as_ref()
+            .unwrap_or_else(|| panic!("Unresolved instance of {:?}", self.geometry))
+    }
+
+    pub fn material(&self) -> &Material {
+        match self.material_override {
+            Some(ref m) => m,
+            None => self.geometry().material(),
+        }
+    }
+}
+
+/// Box spanning `min` to `max`, aligned with the axes before any transform.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AxisAlignedBox {
+    pub min: Point,
+    pub max: Point,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+}
+
+impl AxisAlignedBox {
+    fn extent(&self) -> [f64; 3] {
+        [
+            self.max.x - self.min.x,
+            self.max.y - self.min.y,
+            self.max.z - self.min.z,
+        ]
+    }
+
+    /// Areas of the faces across each axis.
+    fn face_areas(&self) -> [f64; 3] {
+        let e = self.extent();
+        [e[1] * e[2], e[0] * e[2], e[0] * e[1]]
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        2.0 * self.face_areas().iter().sum::<f64>()
+    }
+
+    /// Picks a face in proportion to its area, then a point uniformly on it.
+    pub fn sample_surface(&self, u: f64, v: f64) -> Option<SurfaceSample> {
+        let areas = self.face_areas();
+        let total: f64 = areas.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut scaled = u * total;
+        let mut axis = 0;
+        while axis < 2 && scaled >= areas[axis] {
+            scaled -= areas[axis];
+            axis += 1;
+        }
+        let along = if areas[axis] > 0.0 {
+            (scaled / areas[axis]).min(1.0)
+        } else {
+            0.0
+        };
+        // The first half of the range picks the face at `min`, the second
+        // the one at `max`.
+        let (far, w) = if along < 0.5 {
+            (false, along * 2.0)
+        } else {
+            (true, along * 2.0 - 1.0)
+        };
+
+        let (min, extent) = ([self.min.x, self.min.y, self.min.z], self.extent());
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut point = [0.0; 3];
+        let mut normal = [0.0; 3];
+        point[axis] = if far {
+            min[axis] + extent[axis]
+        } else {
+            min[axis]
+        };
+        point[a] = min[a] + extent[a] * w;
+        point[b] = min[b] + extent[b] * v;
+        normal[axis] = if far { 1.0 } else { -1.0 };
+        Some(SurfaceSample {
+            point: Point {
+                x: point[0],
+                y: point[1],
+                z: point[2],
+            },
+            normal: Vector3 {
+                x: normal[0],
+                y: normal[1],
+                z: normal[2],
+            },
+            pdf: 1.0 / (2.0 * total),
+            detail: HitDetail::default(),
+        })
+    }
+}
+
+fn default_capped() -> bool {
+    true
+}
+
+/// Cylinder standing on `center`, extending `height` along +Y.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Cylinder {
+    /// Center of the bottom cap.
+    pub center: Point,
+    pub radius: f64,
+    pub height: f64,
+    /// Whether the ends are closed by disks.
+    #[serde(default = "default_capped")]
+    pub capped: bool,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+}
+
+/// Cone standing on `center`, with its apex `height` above it along +Y.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Cone {
+    /// Center of the base.
+    pub center: Point,
+    /// Radius of the base.
+    pub radius: f64,
+    pub height: f64,
+    /// Whether the base is closed by a disk.
+    #[serde(default = "default_capped")]
+    pub capped: bool,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+}
+
+/// Flat disk, visible from both sides. Unlike `Plane::normal`, `normal` is
+/// the actual surface normal.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Disk {
+    pub center: Point,
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub normal: Vector3,
+    pub radius: f64,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+}
+
+/// Ring around the Y axis through `center`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Torus {
+    pub center: Point,
+    /// Distance from the center to the middle of the tube.
+    pub major_radius: f64,
+    /// Radius of the tube.
+    pub minor_radius: f64,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// `left` with `right` cut out of it.
+    Difference,
+}
+
+/// Combination of the volumes enclosed by two elements, which may be `Csg`
+/// nodes themselves. Open surfaces such as disks take part as if they were
+/// infinitely thin.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Csg {
+    pub op: CsgOperation,
+    pub left: Box<Element>,
+    pub right: Box<Element>,
+    /// Defaults to the material of `left`.
+    #[serde(default)]
+    pub material: Option<Material>,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+}
+
+impl Csg {
+    pub fn material(&self) -> &Material {
+        match self.material {
+            Some(ref m) => m,
+            None => self.left.material(),
+        }
+    }
 }
 
 pub struct Intersection<'a> {
     pub distance: f64,
     pub element: &'a Element,
+    pub detail: HitDetail,
 }
 
 impl<'a> Intersection<'a> {
-    pub fn new<'b>(distance: f64, element: &'b Element) -> Intersection<'b> {
-        Intersection { distance, element }
+    pub fn new<'b>(distance: f64, element: &'b Element, detail: HitDetail) -> Intersection<'b> {
+        Intersection {
+            distance,
+            element,
+            detail,
+        }
     }
 }
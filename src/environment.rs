@@ -0,0 +1,274 @@
+use std::{
+    f64::consts::PI,
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use image::{codecs::hdr::HdrDecoder, GenericImageView};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    color::{Color, BLACK},
+    sampling::{uniform_sphere, Distribution1D},
+    vector::Vector3,
+};
+
+/// Light arriving from infinitely far away along rays that leave the scene.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Environment {
+    Color(Color),
+    /// Sky blending from `horizon` to `zenith`, with a flat `ground` colour
+    /// below the horizon.
+    Gradient {
+        zenith: Color,
+        horizon: Color,
+        ground: Color,
+    },
+    /// Equirectangular (latitude-longitude) image.
+    Map(#[serde(deserialize_with = "load_environment_map")] EnvironmentMap),
+}
+
+/// A direction towards the environment picked for next-event estimation.
+pub struct EnvironmentSample {
+    pub direction: Vector3,
+    pub radiance: Color,
+    /// Probability density of `direction`, per unit solid angle.
+    pub pdf: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EnvironmentMap {
+    /// `.hdr` and `.exr` files are read as linear radiance; other image
+    /// formats are assumed to be gamma encoded.
+    pub path: PathBuf,
+    /// Rotation around the vertical axis, in degrees.
+    #[serde(default)]
+    pub rotation: f64,
+    /// Multiplier applied to every pixel.
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    width: usize,
+    #[serde(skip_serializing, skip_deserializing)]
+    height: usize,
+    #[serde(skip_serializing, skip_deserializing)]
+    pixels: Vec<Color>,
+    /// Distribution over rows, then one over the pixels of each row, both
+    /// proportional to the luminance reaching the unit sphere.
+    #[serde(skip_serializing, skip_deserializing)]
+    rows: Option<Distribution1D>,
+    #[serde(skip_serializing, skip_deserializing)]
+    columns: Vec<Distribution1D>,
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+impl fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EnvironmentMap({:?})", self.path)
+    }
+}
+
+impl Environment {
+    /// Radiance arriving along a ray travelling in `direction`.
+    pub fn radiance(&self, direction: &Vector3) -> Color {
+        match *self {
+            Environment::Color(ref c) => *c,
+            Environment::Gradient {
+                ref zenith,
+                ref horizon,
+                ref ground,
+            } => {
+                if direction.y < 0.0 {
+                    *ground
+                } else {
+                    let t = direction.y as f32;
+                    *horizon * (1.0 - t) + *zenith * t
+                }
+            }
+            Environment::Map(ref map) => map.radiance(direction),
+        }
+    }
+
+    /// Picks a direction for (`u`, `v`) in [0, 1)². Maps are importance
+    /// sampled by brightness, the other environments uniformly.
+    pub fn sample(&self, u: f64, v: f64) -> EnvironmentSample {
+        match *self {
+            Environment::Map(ref map) => map.sample(u, v),
+            _ => {
+                let direction = uniform_sphere(u, v);
+                EnvironmentSample {
+                    direction,
+                    radiance: self.radiance(&direction),
+                    pdf: 1.0 / (4.0 * PI),
+                }
+            }
+        }
+    }
+
+    /// Density with which `sample` returns `direction`.
+    pub fn pdf(&self, direction: &Vector3) -> f64 {
+        match *self {
+            Environment::Map(ref map) => map.pdf(direction),
+            _ => 1.0 / (4.0 * PI),
+        }
+    }
+}
+
+impl EnvironmentMap {
+    /// Image coordinates in [0, 1)² looked up for `direction`.
+    fn uv(&self, direction: &Vector3) -> (f64, f64) {
+        let phi = direction.x.atan2(-direction.z) + self.rotation.to_radians();
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    fn direction(&self, u: f64, v: f64) -> Vector3 {
+        let phi = (u - 0.5) * 2.0 * PI - self.rotation.to_radians();
+        let theta = v * PI;
+        Vector3 {
+            x: theta.sin() * phi.sin(),
+            y: theta.cos(),
+            z: -theta.sin() * phi.cos(),
+        }
+    }
+
+    fn pixel(&self, u: f64, v: f64) -> (usize, usize) {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        (x, y)
+    }
+
+    fn radiance(&self, direction: &Vector3) -> Color {
+        let (u, v) = self.uv(direction);
+        let (x, y) = self.pixel(u, v);
+        self.pixels[y * self.width + x] * self.intensity
+    }
+
+    fn sample(&self, u: f64, v: f64) -> EnvironmentSample {
+        let rows = self.rows.as_ref().unwrap();
+        let (y, within_y) = rows.sample(v);
+        let (x, within_x) = self.columns[y].sample(u);
+        let map_u = (x as f64 + within_x) / self.width as f64;
+        let map_v = (y as f64 + within_y) / self.height as f64;
+        let direction = self.direction(map_u, map_v);
+        EnvironmentSample {
+            direction,
+            radiance: self.pixels[y * self.width + x] * self.intensity,
+            pdf: self.pdf_at(x, y, map_v),
+        }
+    }
+
+    fn pdf(&self, direction: &Vector3) -> f64 {
+        let (u, v) = self.uv(direction);
+        let (x, y) = self.pixel(u, v);
+        self.pdf_at(x, y, v)
+    }
+
+    /// Converts the density over the image to one over solid angle.
+    fn pdf_at(&self, x: usize, y: usize, v: f64) -> f64 {
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let rows = self.rows.as_ref().unwrap();
+        let probability = rows.probability(y) * self.columns[y].probability(x);
+        let image_pdf = probability * (self.width * self.height) as f64;
+        image_pdf / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn build_distribution(&mut self) {
+        let mut row_weights = Vec::with_capacity(self.height);
+        self.columns = (0..self.height)
+            .map(|y| {
+                let sin_theta = ((y as f64 + 0.5) / self.height as f64 * PI).sin();
+                let weights: Vec<f64> = self.pixels[y * self.width..(y + 1) * self.width]
+                    .iter()
+                    .map(|c| c.luminance() as f64 * sin_theta)
+                    .collect();
+                let row = Distribution1D::new(&weights);
+                row_weights.push(row.total());
+                row
+            })
+            .collect();
+        self.rows = Some(Distribution1D::new(&row_weights));
+    }
+}
+
+/// Reads an image as linear colors, top row first.
+fn read_image(path: &Path) -> Result<(usize, usize, Vec<Color>), String> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match extension.as_deref() {
+        Some("hdr") => {
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            let decoder = HdrDecoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+            let metadata = decoder.metadata();
+            let pixels = decoder.read_image_hdr().map_err(|e| e.to_string())?;
+            let colors = pixels
+                .iter()
+                .map(|p| Color {
+                    red: p[0],
+                    green: p[1],
+                    blue: p[2],
+                })
+                .collect();
+            Ok((metadata.width as usize, metadata.height as usize, colors))
+        }
+        Some("exr") => {
+            let image = exr::prelude::read_first_rgba_layer_from_file(
+                path,
+                |resolution, _| {
+                    (
+                        resolution.width(),
+                        vec![BLACK; resolution.width() * resolution.height()],
+                    )
+                },
+                |(width, colors), position, (red, green, blue, _): (f32, f32, f32, f32)| {
+                    colors[position.y() * *width + position.x()] = Color { red, green, blue };
+                },
+            )
+            .map_err(|e| e.to_string())?;
+            let size = image.layer_data.size;
+            let (_, colors) = image.layer_data.channel_data.pixels;
+            Ok((size.width(), size.height(), colors))
+        }
+        _ => {
+            let img = image::open(path).map_err(|e| e.to_string())?;
+            let colors = img.pixels().map(|(_, _, p)| Color::from_rgba(p)).collect();
+            Ok((img.width() as usize, img.height() as usize, colors))
+        }
+    }
+}
+
+fn load_environment_map<'de, D>(deserializer: D) -> Result<EnvironmentMap, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut map = EnvironmentMap::deserialize(deserializer)?;
+    let (width, height, pixels) = read_image(&map.path).map_err(|e| {
+        ::serde::de::Error::custom(format!(
+            "Unable to load environment map {:?}: {}",
+            map.path, e
+        ))
+    })?;
+    if width == 0 || height == 0 {
+        return Err(::serde::de::Error::custom(format!(
+            "Environment map {:?} is empty",
+            map.path
+        )));
+    }
+    map.width = width;
+    map.height = height;
+    map.pixels = pixels;
+    map.build_distribution();
+    Ok(map)
+}
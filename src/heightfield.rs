@@ -0,0 +1,316 @@
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    bvh::BoundingBox,
+    material::Material,
+    motion::Motion,
+    point::Point,
+    rendering::{slabs, triangle_crossing, Ray, TextureCoords},
+    vector::{Transform, Vector3},
+};
+
+/// Terrain whose heights are read from a grayscale image. It is centered on
+/// the origin, spans `size` along X and rises from 0 to `max_height` along
+/// Y; its depth along Z follows the aspect ratio of the image, whose top row
+/// is at -Z.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Heightfield {
+    pub path: PathBuf,
+    pub size: f64,
+    pub max_height: f64,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub width: usize,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub depth: usize,
+    /// Row-major heights of the grid points.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub heights: Arc<Vec<f64>>,
+    /// Lowest and highest points within blocks of 2ⁿ×2ⁿ cells, from single
+    /// cells up to one block covering the whole terrain.
+    #[serde(skip_serializing, skip_deserializing)]
+    levels: Arc<Vec<Level>>,
+}
+impl fmt::Debug for Heightfield {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Heightfield({:?}, {}×{})",
+            self.path, self.width, self.depth
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Level {
+    columns: usize,
+    rows: usize,
+    ranges: Vec<(f64, f64)>,
+}
+
+impl Heightfield {
+    /// Distance between neighbouring grid points.
+    pub fn spacing(&self) -> f64 {
+        self.size / (self.width - 1) as f64
+    }
+
+    fn height(&self, column: usize, row: usize) -> f64 {
+        self.heights[row * self.width + column]
+    }
+
+    fn vertex(&self, column: usize, row: usize) -> Point {
+        let spacing = self.spacing();
+        Point {
+            x: column as f64 * spacing - self.size / 2.0,
+            y: self.height(column, row),
+            z: row as f64 * spacing - (self.depth - 1) as f64 * spacing / 2.0,
+        }
+    }
+
+    pub fn bounds(&self) -> BoundingBox {
+        let (low, high) = self
+            .levels
+            .last()
+            .map_or((0.0, self.max_height), |l| l.ranges[0]);
+        let half_depth = (self.depth - 1) as f64 * self.spacing() / 2.0;
+        BoundingBox {
+            min: Point {
+                x: -self.size / 2.0,
+                y: low,
+                z: -half_depth,
+            },
+            max: Point {
+                x: self.size / 2.0,
+                y: high,
+                z: half_depth,
+            },
+        }
+    }
+
+    fn build_levels(&mut self) {
+        let mut columns = self.width - 1;
+        let mut rows = self.depth - 1;
+        let mut ranges = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let corners = [
+                    self.height(column, row),
+                    self.height(column + 1, row),
+                    self.height(column, row + 1),
+                    self.height(column + 1, row + 1),
+                ];
+                ranges.push((
+                    corners.iter().cloned().fold(f64::INFINITY, f64::min),
+                    corners.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                ));
+            }
+        }
+        let mut levels = vec![Level {
+            columns,
+            rows,
+            ranges,
+        }];
+
+        while columns > 1 || rows > 1 {
+            let below = levels.last().unwrap();
+            let (next_columns, next_rows) = (columns.div_ceil(2), rows.div_ceil(2));
+            let mut ranges = vec![(f64::INFINITY, f64::NEG_INFINITY); next_columns * next_rows];
+            for row in 0..rows {
+                for column in 0..columns {
+                    let (low, high) = below.ranges[row * columns + column];
+                    let range = &mut ranges[(row / 2) * next_columns + column / 2];
+                    range.0 = range.0.min(low);
+                    range.1 = range.1.max(high);
+                }
+            }
+            columns = next_columns;
+            rows = next_rows;
+            levels.push(Level {
+                columns,
+                rows,
+                ranges,
+            });
+        }
+        self.levels = Arc::new(levels);
+    }
+
+    /// Box around the block of cells at (`column`, `row`) of `level`.
+    fn block_bounds(&self, level: usize, column: usize, row: usize) -> BoundingBox {
+        let cells = 1 << level;
+        let (low, high) = self.levels[level].ranges[row * self.levels[level].columns + column];
+        let first = self.vertex(column * cells, row * cells);
+        let last = self.vertex(
+            ((column + 1) * cells).min(self.width - 1),
+            ((row + 1) * cells).min(self.depth - 1),
+        );
+        BoundingBox {
+            min: Point {
+                x: first.x,
+                y: low,
+                z: first.z,
+            },
+            max: Point {
+                x: last.x,
+                y: high,
+                z: last.z,
+            },
+        }
+    }
+
+    /// Distances at which the line through `ray` crosses the surface. With
+    /// `nearest`, only the closest one in front of the origin is returned,
+    /// and blocks behind it are skipped.
+    pub fn crossings(&self, ray: &Ray, nearest: bool) -> Vec<f64> {
+        let mut crossings = Vec::new();
+        let mut closest = f64::INFINITY;
+        let top = self.levels.len() - 1;
+        let mut stack = vec![(top, 0, 0, f64::NEG_INFINITY)];
+        while let Some((level, column, row, near)) = stack.pop() {
+            if nearest && near > closest {
+                continue;
+            }
+
+            if level == 0 {
+                let corners = (
+                    self.vertex(column, row),
+                    self.vertex(column + 1, row),
+                    self.vertex(column, row + 1),
+                    self.vertex(column + 1, row + 1),
+                );
+                for &triangle in [
+                    (corners.0, corners.1, corners.2),
+                    (corners.1, corners.3, corners.2),
+                ]
+                .iter()
+                {
+                    if let Some((t, _, _)) = triangle_crossing(triangle, ray) {
+                        if !nearest {
+                            crossings.push(t);
+                        } else if t > 0.0 && t < closest {
+                            closest = t;
+                        }
+                    }
+                }
+                continue;
+            }
+
+            // Visit the children closest to the origin first.
+            let below = &self.levels[level - 1];
+            let mut children = Vec::with_capacity(4);
+            for child_row in row * 2..(row * 2 + 2).min(below.rows) {
+                for child_column in column * 2..(column * 2 + 2).min(below.columns) {
+                    let bounds = self.block_bounds(level - 1, child_column, child_row);
+                    if let Some((near, far)) = slabs(&bounds.min, &bounds.max, ray) {
+                        if !nearest || far >= 0.0 {
+                            children.push((level - 1, child_column, child_row, near));
+                        }
+                    }
+                }
+            }
+            children.sort_by(|a, b| b.3.partial_cmp(&a.3).unwrap());
+            stack.extend(children);
+        }
+
+        if nearest && closest.is_finite() {
+            crossings.push(closest);
+        }
+        crossings
+    }
+
+    /// Normal at a grid point, from the slope between its neighbours.
+    fn vertex_normal(&self, column: usize, row: usize) -> Vector3 {
+        let left = self.height(column.saturating_sub(1), row);
+        let right = self.height((column + 1).min(self.width - 1), row);
+        let back = self.height(column, row.saturating_sub(1));
+        let front = self.height(column, (row + 1).min(self.depth - 1));
+        let spacing = self.spacing();
+        Vector3 {
+            x: (left - right) / (2.0 * spacing),
+            y: 1.0,
+            z: (back - front) / (2.0 * spacing),
+        }
+        .normalize()
+    }
+
+    /// Cell containing the point above (`x`, `z`), and the position within
+    /// it.
+    fn locate(&self, point: &Point) -> (usize, usize, f64, f64) {
+        let spacing = self.spacing();
+        let u = (point.x + self.size / 2.0) / spacing;
+        let v = (point.z + (self.depth - 1) as f64 * spacing / 2.0) / spacing;
+        let column = (u.max(0.0) as usize).min(self.width - 2);
+        let row = (v.max(0.0) as usize).min(self.depth - 2);
+        (
+            column,
+            row,
+            (u - column as f64).clamp(0.0, 1.0),
+            (v - row as f64).clamp(0.0, 1.0),
+        )
+    }
+
+    /// Vertex normals interpolated across the cell under `point`.
+    pub fn normal_at(&self, point: &Point) -> Vector3 {
+        let (column, row, s, t) = self.locate(point);
+        let blend = |a: Vector3, b: Vector3, weight: f64| a * (1.0 - weight) + b * weight;
+        let back = blend(
+            self.vertex_normal(column, row),
+            self.vertex_normal(column + 1, row),
+            s,
+        );
+        let front = blend(
+            self.vertex_normal(column, row + 1),
+            self.vertex_normal(column + 1, row + 1),
+            s,
+        );
+        blend(back, front, t).normalize()
+    }
+
+    /// The whole image is mapped once over the terrain.
+    pub fn texture_coords_at(&self, point: &Point) -> TextureCoords {
+        let (column, row, s, t) = self.locate(point);
+        TextureCoords {
+            x: ((column as f64 + s) / (self.width - 1) as f64) as f32,
+            y: ((row as f64 + t) / (self.depth - 1) as f64) as f32,
+        }
+    }
+}
+
+pub fn load_heightfield<'de, D>(deserializer: D) -> Result<Heightfield, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut heightfield = Heightfield::deserialize(deserializer)?;
+    let image = match image::open(&heightfield.path) {
+        Ok(image) => image.to_luma16(),
+        Err(e) => {
+            return Err(::serde::de::Error::custom(format!(
+                "Unable to open heightfield file {:?}: {}",
+                heightfield.path, e
+            )))
+        }
+    };
+    if image.width() < 2 || image.height() < 2 {
+        return Err(::serde::de::Error::custom(format!(
+            "Heightfield {:?} needs at least 2×2 pixels",
+            heightfield.path
+        )));
+    }
+
+    heightfield.width = image.width() as usize;
+    heightfield.depth = image.height() as usize;
+    heightfield.heights = Arc::new(
+        image
+            .pixels()
+            .map(|p| p[0] as f64 / u16::MAX as f64 * heightfield.max_height)
+            .collect(),
+    );
+    heightfield.build_levels();
+    Ok(heightfield)
+}
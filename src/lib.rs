@@ -1,35 +1,55 @@
 #[macro_use]
 extern crate serde_derive;
 
+pub mod animation;
+pub mod brdf;
+pub mod bvh;
+pub mod camera;
 pub mod color;
+pub mod csg;
 pub mod element;
+pub mod environment;
+pub mod heightfield;
 pub mod light;
 pub mod material;
+pub mod mesh;
+pub mod motion;
+pub mod output;
+mod pathtracer;
 pub mod point;
 mod rendering;
+pub mod sampling;
 pub mod scene;
+pub mod sdf;
+pub mod tonemap;
 pub mod vector;
 
 use std::sync::Arc;
 
-use color::Color;
+use camera::View;
+use color::{Color, BLACK};
+use pathtracer::trace_path;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
 use rayon::prelude::*;
 use rendering::{cast_ray, Ray};
+use sampling::FilterSampler;
+use scene::{Integrator, Scene};
+use tonemap::ToneMapping;
 
-#[derive(Debug)]
-struct RayTask {
-    x: u32,
-    y: u32,
-    color: Color,
-}
-
-pub fn render(scene: scene::Scene, buffer: &mut [u8], bytes_per_pixel: u8) {
+/// Renders the scene into `buffer`, which holds 8-bit RGB or RGBA pixels in
+/// row-major order. Fails on scenes that refer to unknown geometries or
+/// otherwise can't be rendered.
+pub fn render(scene: Scene, buffer: &mut [u8], bytes_per_pixel: u8) -> Result<(), String> {
     if bytes_per_pixel != 3 && bytes_per_pixel != 4 {
-        // TODO: Maybe return a result?
-        return;
+        return Err(format!(
+            "Unsupported pixel size of {} bytes",
+            bytes_per_pixel
+        ));
     }
 
-    let scene = Arc::new(scene);
+    let scene = prepare(scene)?;
+    let filter = FilterSampler::new(scene.filter);
+    let views = scene.camera.views(scene.width, scene.height);
     let write_pixel = match bytes_per_pixel {
         4 => crate::write_rgba_pixel,
         3 => crate::write_rgb_pixel,
@@ -43,25 +63,113 @@ pub fn render(scene: scene::Scene, buffer: &mut [u8], bytes_per_pixel: u8) {
             let x = i % scene.width as usize;
             let y = i / scene.width as usize;
 
-            let ray = Ray::create_prime(x as u64, y as u64, scene.clone());
-            let color = cast_ray(scene.clone(), &ray, 0);
-            write_pixel(color, pixel);
+            let color = render_pixel(scene.clone(), &filter, &views, x as u32, y as u32);
+            write_pixel(color, &scene.tone_mapping, pixel);
         });
+    Ok(())
+}
+
+/// Renders the scene into linear, unclamped colors in row-major order, for
+/// high dynamic range output.
+pub fn render_hdr(scene: Scene) -> Result<Vec<Color>, String> {
+    let scene = prepare(scene)?;
+    let filter = FilterSampler::new(scene.filter);
+    let views = scene.camera.views(scene.width, scene.height);
+    let width = scene.width as usize;
+
+    Ok((0..width * scene.height as usize)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = ((i % width) as u32, (i / width) as u32);
+            render_pixel(scene.clone(), &filter, &views, x, y)
+        })
+        .collect())
+}
+
+fn prepare(mut scene: Scene) -> Result<Arc<Scene>, String> {
+    scene.camera.validate()?;
+    scene.resolve_instances()?;
+    if scene.acceleration == scene::Acceleration::Bvh && scene.bvh.is_none() {
+        scene.build_bvh();
+    }
+    scene.collect_emitters();
+    Ok(Arc::new(scene))
+}
+
+/// Averages `samples_per_pixel` camera rays around the pixel, weighted by the
+/// scene's reconstruction filter. Every sample uses the one of `views` the
+/// pixel center falls in, even if the filter reaches into another one.
+fn render_pixel(
+    scene: Arc<Scene>,
+    filter: &FilterSampler,
+    views: &[View],
+    x: u32,
+    y: u32,
+) -> Color {
+    let center_x = x as f64 + 0.5;
+    let center_y = y as f64 + 0.5;
+    // Seeded per pixel so renders are reproducible.
+    let mut rng = SmallRng::seed_from_u64(y as u64 * scene.width as u64 + x as u64);
+    let view = match views.iter().find(|v| v.contains(center_x, center_y)) {
+        Some(view) => view,
+        None => return BLACK,
+    };
+    let times = scene
+        .camera
+        .sample_times(scene.samples_per_pixel.max(1), &mut rng);
+    if scene.samples_per_pixel <= 1 {
+        let lens = scene.camera.sample_lens(&mut rng);
+        return match Ray::create_prime(center_x, center_y, lens, times[0], view, scene.clone()) {
+            Some(ray) => radiance(scene, &ray, &mut rng),
+            None => BLACK,
+        };
+    }
+
+    let mut color = BLACK;
+    let mut unweighted = BLACK;
+    let mut total_weight = 0.0;
+    let samples = sampling::stratified_samples(scene.samples_per_pixel, &mut rng);
+    for ((u, v), time) in samples.into_iter().zip(times) {
+        let (dx, dy, weight) = filter.sample(u, v);
+        let (x, y) = (center_x + dx, center_y + dy);
+        let lens = scene.camera.sample_lens(&mut rng);
+        let sample = match Ray::create_prime(x, y, lens, time, view, scene.clone()) {
+            Some(ray) => radiance(scene.clone(), &ray, &mut rng),
+            None => BLACK,
+        };
+        color = color + sample * weight as f32;
+        unweighted = unweighted + sample;
+        total_weight += weight as f32;
+    }
+
+    if total_weight != 0.0 {
+        color * total_weight.recip()
+    } else {
+        // Negative lobe samples cancelled out the positive ones exactly.
+        unweighted * (scene.samples_per_pixel as f32).recip()
+    }
+}
+
+fn radiance<R: Rng>(scene: Arc<Scene>, ray: &Ray, rng: &mut R) -> Color {
+    match scene.integrator {
+        Integrator::Whitted => cast_ray(scene, ray, 0, rng),
+        Integrator::PathTracer => trace_path(scene, ray, rng),
+    }
 }
 
-fn write_rgba_pixel(color: Color, pixel: &mut [u8]) {
-    let rgba = color.to_rgba();
+fn write_rgba_pixel(color: Color, tone_mapping: &ToneMapping, pixel: &mut [u8]) {
+    let rgba = color.to_rgba(tone_mapping);
     pixel[0] = rgba[0];
     pixel[1] = rgba[1];
     pixel[2] = rgba[2];
     pixel[3] = rgba[3];
 }
 
-fn write_rgb_pixel(color: Color, pixel: &mut [u8]) {
-    let rgb = color.to_rgb();
+fn write_rgb_pixel(color: Color, tone_mapping: &ToneMapping, pixel: &mut [u8]) {
+    let rgb = color.to_rgb(tone_mapping);
     pixel[0] = rgb[0];
     pixel[1] = rgb[1];
     pixel[2] = rgb[2];
 }
 
-fn write_dummy_pixel(_: Color, _: &mut [u8]) {}
+fn write_dummy_pixel(_: Color, _: &ToneMapping, _: &mut [u8]) {}
@@ -1,4 +1,12 @@
-use crate::{color::Color, point::Point, vector::Vector3};
+use std::f32::consts::PI;
+
+use crate::{
+    color::Color,
+    point::Point,
+    rendering::Ray,
+    sampling::{concentric_disk, tangent_frame},
+    vector::Vector3,
+};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DirectionalLight {
@@ -15,40 +23,336 @@ pub struct SphericalLight {
     pub intensity: f32,
 }
 
+/// Point light restricted to a cone around `direction`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SpotLight {
+    pub position: Point,
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub direction: Vector3,
+    /// Half-angle in degrees inside which the light is at full strength.
+    pub inner_angle: f64,
+    /// Half-angle in degrees beyond which no light is emitted.
+    pub outer_angle: f64,
+    pub color: Color,
+    /// Power of the matching `SphericalLight`, so both are equally bright
+    /// inside the cone.
+    pub intensity: f32,
+}
+
+impl SpotLight {
+    /// Smoothstep from the outer to the inner cone.
+    fn falloff(&self, direction: &Vector3) -> f32 {
+        let cos_angle = self.direction.dot(direction);
+        let cos_outer = self.outer_angle.to_radians().cos();
+        let cos_inner = self.inner_angle.min(self.outer_angle).to_radians().cos();
+        if cos_angle <= cos_outer {
+            return 0.0;
+        }
+        if cos_angle >= cos_inner {
+            return 1.0;
+        }
+        let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer)) as f32;
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+fn default_light_samples() -> u32 {
+    16
+}
+
+/// Parallelogram emitting on the side `u × v` points to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RectangleLight {
+    /// Center of the rectangle.
+    pub position: Point,
+    /// Full-length edge vectors.
+    pub u: Vector3,
+    pub v: Vector3,
+    pub color: Color,
+    /// Total emitted power, like `SphericalLight::intensity`.
+    pub intensity: f32,
+    /// Shadow rays cast towards the light per shaded point.
+    #[serde(default = "default_light_samples")]
+    pub samples: u32,
+}
+
+/// Disk emitting on the side its normal points to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DiskLight {
+    pub position: Point,
+    #[serde(deserialize_with = "Vector3::deserialize_normalized")]
+    pub normal: Vector3,
+    pub radius: f64,
+    pub color: Color,
+    pub intensity: f32,
+    #[serde(default = "default_light_samples")]
+    pub samples: u32,
+}
+
+/// Sphere with a radius, unlike the point-like `SphericalLight`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SphereLight {
+    pub position: Point,
+    pub radius: f64,
+    pub color: Color,
+    pub intensity: f32,
+    #[serde(default = "default_light_samples")]
+    pub samples: u32,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Light {
     Directional(DirectionalLight),
     Spherical(SphericalLight),
+    Spot(SpotLight),
+    Rectangle(RectangleLight),
+    Disk(DiskLight),
+    Sphere(SphereLight),
 }
+
+/// A point on a light as seen from a shaded point.
+pub struct LightSample {
+    pub direction: Vector3,
+    pub distance: f64,
+    /// Light arriving from the sample at normal incidence.
+    pub intensity: f32,
+}
+
 impl Light {
     pub fn color(&self) -> Color {
         match *self {
             Light::Directional(ref d) => d.color,
             Light::Spherical(ref s) => s.color,
+            Light::Spot(ref s) => s.color,
+            Light::Rectangle(ref r) => r.color,
+            Light::Disk(ref d) => d.color,
+            Light::Sphere(ref s) => s.color,
         }
     }
 
-    pub fn direction_from(&self, hit_point: &Point) -> Vector3 {
+    pub fn color_mut(&mut self) -> &mut Color {
+        match *self {
+            Light::Directional(ref mut d) => &mut d.color,
+            Light::Spherical(ref mut s) => &mut s.color,
+            Light::Spot(ref mut s) => &mut s.color,
+            Light::Rectangle(ref mut r) => &mut r.color,
+            Light::Disk(ref mut d) => &mut d.color,
+            Light::Sphere(ref mut s) => &mut s.color,
+        }
+    }
+
+    pub fn intensity_mut(&mut self) -> &mut f32 {
         match *self {
-            Light::Directional(ref d) => -d.direction,
-            Light::Spherical(ref s) => (s.position - *hit_point).normalize(),
+            Light::Directional(ref mut d) => &mut d.intensity,
+            Light::Spherical(ref mut s) => &mut s.intensity,
+            Light::Spot(ref mut s) => &mut s.intensity,
+            Light::Rectangle(ref mut r) => &mut r.intensity,
+            Light::Disk(ref mut d) => &mut d.intensity,
+            Light::Sphere(ref mut s) => &mut s.intensity,
         }
     }
 
+    pub fn direction_from(&self, hit_point: &Point) -> Vector3 {
+        self.sample(hit_point, 0.5, 0.5).direction
+    }
+
     pub fn intensity(&self, hit_point: &Point) -> f32 {
+        self.sample(hit_point, 0.5, 0.5).intensity
+    }
+
+    pub fn distance(&self, hit_point: &Point) -> f64 {
+        self.sample(hit_point, 0.5, 0.5).distance
+    }
+
+    /// Number of samples `sample` should be averaged over.
+    pub fn sample_count(&self) -> u32 {
+        match *self {
+            Light::Directional(_) | Light::Spherical(_) | Light::Spot(_) => 1,
+            Light::Rectangle(ref r) => r.samples.max(1),
+            Light::Disk(ref d) => d.samples.max(1),
+            Light::Sphere(ref s) => s.samples.max(1),
+        }
+    }
+
+    /// Picks the point of the light at (`u`, `v`) in [0, 1)², as seen from
+    /// `hit_point`. Point-like lights ignore `u` and `v`.
+    pub fn sample(&self, hit_point: &Point, u: f64, v: f64) -> LightSample {
         match *self {
-            Light::Directional(ref d) => d.intensity,
+            Light::Directional(ref d) => LightSample {
+                direction: -d.direction,
+                distance: f64::INFINITY,
+                intensity: d.intensity,
+            },
             Light::Spherical(ref s) => {
                 let r2 = (s.position - *hit_point).norm() as f32;
-                s.intensity / (4.0 * ::std::f32::consts::PI * r2)
+                LightSample {
+                    direction: (s.position - *hit_point).normalize(),
+                    distance: (s.position - *hit_point).length(),
+                    intensity: s.intensity / (4.0 * PI * r2),
+                }
+            }
+            Light::Spot(ref s) => {
+                let to_light = s.position - *hit_point;
+                let distance = to_light.length();
+                let direction = to_light * distance.recip();
+                let r2 = (distance * distance) as f32;
+                LightSample {
+                    direction,
+                    distance,
+                    intensity: s.intensity * s.falloff(&-direction) / (4.0 * PI * r2),
+                }
+            }
+            Light::Rectangle(ref r) => {
+                let point = r.position + r.u * (u - 0.5) + r.v * (v - 0.5);
+                let normal = r.u.cross(&r.v);
+                let area = normal.length();
+                sample_area(hit_point, point, normal.normalize(), area, r.intensity)
+            }
+            Light::Disk(ref d) => {
+                let (x, y) = concentric_disk(u, v);
+                let (tangent, bitangent) = tangent_frame(&d.normal);
+                let point = d.position + (tangent * x + bitangent * y) * d.radius;
+                let area = std::f64::consts::PI * d.radius * d.radius;
+                sample_area(hit_point, point, d.normal, area, d.intensity)
             }
+            Light::Sphere(ref s) => sample_sphere(s, hit_point, u, v),
         }
     }
 
-    pub fn distance(&self, hit_point: &Point) -> f64 {
+    /// Radiance leaving the visible surface of area lights.
+    pub fn emission(&self) -> Color {
+        let radiance = match *self {
+            Light::Directional(_) | Light::Spherical(_) | Light::Spot(_) => 0.0,
+            Light::Rectangle(ref r) => r.intensity / (PI * r.u.cross(&r.v).length() as f32),
+            Light::Disk(ref d) => d.intensity / (PI * PI * (d.radius * d.radius) as f32),
+            Light::Sphere(ref s) => s.intensity / (4.0 * PI * PI * (s.radius * s.radius) as f32),
+        };
+        self.color() * radiance
+    }
+
+    /// Distance along `ray` to the emitting side of an area light; point-like
+    /// lights can't be hit.
+    pub fn intersect(&self, ray: &Ray) -> Option<f64> {
         match *self {
-            Light::Directional(_) => ::std::f64::INFINITY,
-            Light::Spherical(ref s) => (s.position - *hit_point).length(),
+            Light::Directional(_) | Light::Spherical(_) | Light::Spot(_) => None,
+            Light::Rectangle(ref r) => {
+                let normal = r.u.cross(&r.v).normalize();
+                let distance = intersect_front(ray, &r.position, &normal)?;
+                let offset = (ray.origin + ray.direction * distance) - r.position;
+                // Solves offset = s u + t v, which also holds for skewed
+                // edges, unlike projecting onto each edge on its own.
+                let (uu, uv, vv) = (r.u.norm(), r.u.dot(&r.v), r.v.norm());
+                let (ou, ov) = (offset.dot(&r.u), offset.dot(&r.v));
+                let det = uu * vv - uv * uv;
+                let s = (vv * ou - uv * ov) / det;
+                let t = (uu * ov - uv * ou) / det;
+                if s.abs() <= 0.5 && t.abs() <= 0.5 {
+                    Some(distance)
+                } else {
+                    None
+                }
+            }
+            Light::Disk(ref d) => {
+                let distance = intersect_front(ray, &d.position, &d.normal)?;
+                let offset = (ray.origin + ray.direction * distance) - d.position;
+                if offset.norm() <= d.radius * d.radius {
+                    Some(distance)
+                } else {
+                    None
+                }
+            }
+            Light::Sphere(ref s) => {
+                let l = s.position - ray.origin;
+                let adj = l.dot(&ray.direction);
+                let d2 = l.norm() - adj * adj;
+                let radius2 = s.radius * s.radius;
+                if d2 > radius2 {
+                    return None;
+                }
+                let thc = (radius2 - d2).sqrt();
+                let t0 = adj - thc;
+                if t0 > 0.0 {
+                    Some(t0)
+                } else {
+                    None
+                }
+            }
         }
     }
 }
+
+/// Distance to a plane through `origin`, only when approaching it from the
+/// side `normal` points to.
+fn intersect_front(ray: &Ray, origin: &Point, normal: &Vector3) -> Option<f64> {
+    let denom = normal.dot(&ray.direction);
+    if denom >= -1e-6 {
+        return None;
+    }
+    let distance = (*origin - ray.origin).dot(normal) / denom;
+    if distance > 0.0 {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+/// Sample of a one-sided, uniformly emitting planar light of total `power`.
+fn sample_area(
+    hit_point: &Point,
+    point: Point,
+    normal: Vector3,
+    area: f64,
+    power: f32,
+) -> LightSample {
+    let to_light = point - *hit_point;
+    let distance = to_light.length();
+    let direction = to_light * distance.recip();
+    let cos_light = (-direction).dot(&normal).max(0.0);
+    let radiance = power / (PI * area as f32);
+    LightSample {
+        direction,
+        distance,
+        // Converts the area density of the sample to solid angle.
+        intensity: radiance * (cos_light * area / (distance * distance)) as f32,
+    }
+}
+
+/// Samples the cone of directions under which the sphere is visible, which
+/// is noticeably less noisy than sampling its whole surface.
+fn sample_sphere(light: &SphereLight, hit_point: &Point, u: f64, v: f64) -> LightSample {
+    let to_center = light.position - *hit_point;
+    let center_distance = to_center.length();
+    if center_distance <= light.radius {
+        // Inside the light; it doesn't illuminate its own interior.
+        return LightSample {
+            direction: to_center * center_distance.recip(),
+            distance: 0.0,
+            intensity: 0.0,
+        };
+    }
+
+    let axis = to_center * center_distance.recip();
+    let sin_max = light.radius / center_distance;
+    let cos_max = (1.0 - sin_max * sin_max).max(0.0).sqrt();
+    let cos_theta = 1.0 - u * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * v;
+    let (tangent, bitangent) = tangent_frame(&axis);
+    let direction = (tangent * (sin_theta * phi.cos())
+        + bitangent * (sin_theta * phi.sin())
+        + axis * cos_theta)
+        .normalize();
+
+    // Distance to the near side of the sphere along the sampled direction.
+    let adj = to_center.dot(&direction);
+    let d2 = (to_center.norm() - adj * adj).max(0.0);
+    let distance = adj - (light.radius * light.radius - d2).max(0.0).sqrt();
+
+    let radiance = light.intensity / (4.0 * PI * PI * (light.radius * light.radius) as f32);
+    let solid_angle = 2.0 * std::f64::consts::PI * (1.0 - cos_max);
+    LightSample {
+        direction,
+        distance,
+        intensity: radiance * solid_angle as f32,
+    }
+}
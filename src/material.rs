@@ -3,12 +3,36 @@ use std::{fmt, path::PathBuf};
 use image::{DynamicImage, GenericImageView};
 use serde::{Deserialize, Deserializer};
 
-use crate::{color::Color, rendering::TextureCoords};
+use crate::{
+    color::{Color, BLACK},
+    rendering::TextureCoords,
+};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum SurfaceType {
     Diffuse,
     Reflective { reflectivity: f32 },
+    /// Dielectric such as glass or water. `index` is the index of refraction
+    /// and `transparency` blends between diffuse shading (0) and fully
+    /// transmissive/reflective (1).
+    Refractive { index: f32, transparency: f32 },
+    /// Metallic/roughness microfacet model with a GGX distribution. Ignores
+    /// `Material::coloration` and `Material::albedo`.
+    Principled {
+        base_color: Coloration,
+        /// 0 for dielectrics, 1 for metals.
+        metallic: Parameter,
+        /// Perceptual roughness; 0 is a perfectly smooth surface.
+        roughness: Parameter,
+        /// Reflectance of dielectrics at normal incidence, scaled so that 0.5
+        /// gives the common 4%.
+        #[serde(default = "default_specular")]
+        specular: f32,
+    },
+}
+
+fn default_specular() -> f32 {
+    0.5
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -16,6 +40,37 @@ pub struct Material {
     pub coloration: Coloration,
     pub albedo: f32,
     pub surface: SurfaceType,
+    /// Color of the Blinn-Phong highlight added to the diffuse term; no
+    /// highlight when missing.
+    #[serde(default)]
+    pub specular_color: Option<Color>,
+    /// Blinn-Phong exponent; higher values give smaller, sharper highlights.
+    #[serde(default = "default_shininess")]
+    pub shininess: f32,
+    /// Light given off by the surface itself, on both sides.
+    #[serde(default)]
+    pub emission: Option<Coloration>,
+    /// Multiplier for `emission`, so bright emitters can still use textures.
+    #[serde(default = "default_emission_strength")]
+    pub emission_strength: f32,
+}
+
+fn default_emission_strength() -> f32 {
+    1.0
+}
+
+impl Material {
+    /// Emitted radiance at `coords`; black for non-emissive materials.
+    pub fn emission(&self, coords: &TextureCoords) -> Color {
+        match self.emission {
+            Some(ref e) => e.color(coords) * self.emission_strength,
+            None => BLACK,
+        }
+    }
+}
+
+fn default_shininess() -> f32 {
+    32.0
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -71,13 +126,35 @@ fn wrap(val: f32, bound: u32) -> u32 {
 impl Coloration {
     pub fn color(&self, coords: &TextureCoords) -> Color {
         match *self {
-            Coloration::Color(ref c) => c.clone(),
+            Coloration::Color(ref c) => *c,
             Coloration::Texture(ref tex) => {
                 let tex_x = wrap(coords.x, tex.texture.width());
                 let tex_y = wrap(coords.y, tex.texture.height());
 
-                Color::from_rgba(tex.texture.get_pixel(tex_x, tex_y)).clone()
+                Color::from_rgba(tex.texture.get_pixel(tex_x, tex_y))
+            }
+        }
+    }
+}
+
+/// Scalar material input, either constant or read from the first channel of
+/// a (linear) texture.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Parameter {
+    Value(f32),
+    Texture(#[serde(deserialize_with = "load_texture")] Texture),
+}
+
+impl Parameter {
+    pub fn value(&self, coords: &TextureCoords) -> f32 {
+        match *self {
+            Parameter::Value(v) => v,
+            Parameter::Texture(ref tex) => {
+                let tex_x = wrap(coords.x, tex.texture.width());
+                let tex_y = wrap(coords.y, tex.texture.height());
+
+                tex.texture.get_pixel(tex_x, tex_y)[0] as f32 / 255.0
             }
         }
     }
-}
\ No newline at end of file
+}
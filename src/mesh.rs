@@ -0,0 +1,396 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    bvh::{BoundingBox, Bvh},
+    element::SurfaceSample,
+    material::Material,
+    motion::Motion,
+    point::Point,
+    rendering::{HitDetail, TextureCoords},
+    vector::{Transform, Vector3},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle {
+    pub positions: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+}
+
+/// The geometry read from `path` is shared between clones, so copying a
+/// scene for each frame of an animation doesn't copy every triangle.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Mesh {
+    pub path: PathBuf,
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub positions: Arc<Vec<Point>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub normals: Arc<Vec<Vector3>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub uvs: Arc<Vec<TextureCoords>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub triangles: Arc<Vec<Triangle>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub bvh: Arc<Bvh>,
+}
+impl fmt::Debug for Mesh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Mesh({:?}, {} triangles)",
+            self.path,
+            self.triangles.len()
+        )
+    }
+}
+
+/// Barycentric coordinates of a hit on a single triangle of a mesh.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TriangleHit {
+    pub triangle: usize,
+    pub u: f64,
+    pub v: f64,
+}
+
+impl Mesh {
+    pub fn vertices(&self, triangle: &Triangle) -> (Point, Point, Point) {
+        (
+            self.positions[triangle.positions[0]],
+            self.positions[triangle.positions[1]],
+            self.positions[triangle.positions[2]],
+        )
+    }
+
+    /// Rebuilds the triangle hierarchy; needed after editing the geometry.
+    pub fn build_bvh(&mut self) {
+        let bounds: Vec<BoundingBox> = self
+            .triangles
+            .iter()
+            .map(|t| {
+                let (p0, p1, p2) = self.vertices(t);
+                BoundingBox::from_points(vec![p0, p1, p2])
+            })
+            .collect();
+        self.bvh = Arc::new(Bvh::build(&bounds));
+    }
+
+    pub fn triangle_area(&self, triangle: &Triangle) -> f64 {
+        let (p0, p1, p2) = self.vertices(triangle);
+        (p1 - p0).cross(&(p2 - p0)).length() / 2.0
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        self.triangles.iter().map(|t| self.triangle_area(t)).sum()
+    }
+
+    /// Picks a triangle uniformly, then a point uniformly on it. Cheap, but
+    /// noisy for meshes with very uneven triangle sizes.
+    pub fn sample_surface(&self, u: f64, v: f64) -> Option<SurfaceSample> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+        let count = self.triangles.len();
+        let scaled = u * count as f64;
+        let index = (scaled as usize).min(count - 1);
+        let u = scaled - index as f64;
+
+        let triangle = &self.triangles[index];
+        let (p0, p1, p2) = self.vertices(triangle);
+        let su = u.sqrt();
+        let (b1, b2) = ((1.0 - v) * su, v * su);
+        let area = self.triangle_area(triangle);
+        if area == 0.0 {
+            return None;
+        }
+        let hit = TriangleHit {
+            triangle: index,
+            u: b1,
+            v: b2,
+        };
+        Some(SurfaceSample {
+            point: p0 + (p1 - p0) * b1 + (p2 - p0) * b2,
+            normal: self.normal_at(&hit),
+            pdf: 1.0 / (count as f64 * area),
+            detail: HitDetail {
+                triangle: Some(hit),
+                ..HitDetail::default()
+            },
+        })
+    }
+
+    /// Density per unit area with which `sample_surface` picks the point
+    /// at `hit`.
+    pub fn surface_pdf(&self, hit: &TriangleHit) -> f64 {
+        let area = self.triangle_area(&self.triangles[hit.triangle]);
+        if area == 0.0 {
+            0.0
+        } else {
+            1.0 / (self.triangles.len() as f64 * area)
+        }
+    }
+
+    pub fn face_normal(&self, triangle: &Triangle) -> Vector3 {
+        let (p0, p1, p2) = self.vertices(triangle);
+        (p1 - p0).cross(&(p2 - p0)).normalize()
+    }
+
+    pub fn normal_at(&self, hit: &TriangleHit) -> Vector3 {
+        let triangle = &self.triangles[hit.triangle];
+        match triangle.normals {
+            Some([n0, n1, n2]) => {
+                let w = 1.0 - hit.u - hit.v;
+                (self.normals[n0] * w + self.normals[n1] * hit.u + self.normals[n2] * hit.v)
+                    .normalize()
+            }
+            None => self.face_normal(triangle),
+        }
+    }
+
+    pub fn texture_coords_at(&self, hit: &TriangleHit) -> TextureCoords {
+        let triangle = &self.triangles[hit.triangle];
+        match triangle.uvs {
+            Some([t0, t1, t2]) => {
+                let w = 1.0 - hit.u - hit.v;
+                let (t0, t1, t2) = (&self.uvs[t0], &self.uvs[t1], &self.uvs[t2]);
+                TextureCoords {
+                    x: t0.x * w as f32 + t1.x * hit.u as f32 + t2.x * hit.v as f32,
+                    y: t0.y * w as f32 + t1.y * hit.u as f32 + t2.y * hit.v as f32,
+                }
+            }
+            None => TextureCoords {
+                x: hit.u as f32,
+                y: hit.v as f32,
+            },
+        }
+    }
+}
+
+/// Resolves a 1-based (or negative, relative) OBJ index into a 0-based one.
+fn obj_index(token: &str, len: usize) -> Result<usize, String> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| format!("Invalid index: {:?}", token))?;
+    let resolved = if index < 0 {
+        len as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(format!("Index out of range: {}", index));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_floats(tokens: &[&str], count: usize) -> Result<Vec<f64>, String> {
+    if tokens.len() < count {
+        return Err(format!("Expected {} values, got {}", count, tokens.len()));
+    }
+    tokens[..count]
+        .iter()
+        .map(|t| t.parse().map_err(|_| format!("Invalid number: {:?}", t)))
+        .collect()
+}
+
+/// Parses the geometry of a Wavefront OBJ file into `mesh`.
+///
+/// Only `v`, `vt`, `vn` and `f` statements are used; polygons are
+/// triangulated as fans. Everything else (groups, materials, ...) is ignored.
+pub fn parse_obj<R: BufRead>(reader: R, mesh: &mut Mesh) -> Result<(), String> {
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let at_line = |e: String| format!("line {}: {}", line_number + 1, e);
+
+        match tokens.first() {
+            Some(&"v") => {
+                let v = parse_floats(&tokens[1..], 3).map_err(at_line)?;
+                Arc::make_mut(&mut mesh.positions).push(Point {
+                    x: v[0],
+                    y: v[1],
+                    z: v[2],
+                });
+            }
+            Some(&"vn") => {
+                let v = parse_floats(&tokens[1..], 3).map_err(at_line)?;
+                Arc::make_mut(&mut mesh.normals).push(
+                    Vector3 {
+                        x: v[0],
+                        y: v[1],
+                        z: v[2],
+                    }
+                    .normalize(),
+                );
+            }
+            Some(&"vt") => {
+                // `vt u [v [w]]`, where v defaults to 0.
+                let count = tokens.len().clamp(2, 3) - 1;
+                let v = parse_floats(&tokens[1..], count).map_err(at_line)?;
+                // OBJ texture space has v pointing up, images have y pointing down.
+                Arc::make_mut(&mut mesh.uvs).push(TextureCoords {
+                    x: v[0] as f32,
+                    y: 1.0 - v.get(1).cloned().unwrap_or(0.0) as f32,
+                });
+            }
+            Some(&"f") => {
+                let corners = tokens[1..]
+                    .iter()
+                    .map(|corner| parse_face_corner(corner, mesh))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(at_line)?;
+                if corners.len() < 3 {
+                    return Err(at_line("Face has fewer than 3 vertices".to_string()));
+                }
+
+                for i in 1..corners.len() - 1 {
+                    let (a, b, c) = (corners[0], corners[i], corners[i + 1]);
+                    Arc::make_mut(&mut mesh.triangles).push(Triangle {
+                        positions: [a.0, b.0, c.0],
+                        normals: match (a.2, b.2, c.2) {
+                            (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                            _ => None,
+                        },
+                        uvs: match (a.1, b.1, c.1) {
+                            (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                            _ => None,
+                        },
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+type FaceCorner = (usize, Option<usize>, Option<usize>);
+
+/// Parses a face corner of the form `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+fn parse_face_corner(corner: &str, mesh: &Mesh) -> Result<FaceCorner, String> {
+    let mut parts = corner.split('/');
+    let position = obj_index(parts.next().unwrap_or(""), mesh.positions.len())?;
+    let uv = match parts.next() {
+        Some(t) if !t.is_empty() => Some(obj_index(t, mesh.uvs.len())?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(n) if !n.is_empty() => Some(obj_index(n, mesh.normals.len())?),
+        _ => None,
+    };
+    Ok((position, uv, normal))
+}
+
+fn read_obj(path: &Path, mesh: &mut Mesh) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    parse_obj(BufReader::new(file), mesh)
+}
+
+pub fn load_mesh<'de, D>(deserializer: D) -> Result<Mesh, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut mesh = Mesh::deserialize(deserializer)?;
+    let path = mesh.path.clone();
+    if let Err(e) = read_obj(&path, &mut mesh) {
+        Err(::serde::de::Error::custom(format!(
+            "Unable to load mesh file {:?}: {}",
+            path, e
+        )))
+    } else {
+        mesh.build_bvh();
+        Ok(mesh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(obj: &str) -> Mesh {
+        let mut mesh: Mesh = serde_json::from_str(
+            r#"{"path": "test.obj",
+                "material": {"coloration": {"Color": {"red": 1.0, "green": 1.0, "blue": 1.0}},
+                             "albedo": 0.5, "surface": "Diffuse"}}"#,
+        )
+        .unwrap();
+        parse_obj(obj.as_bytes(), &mut mesh).unwrap();
+        mesh
+    }
+
+    #[test]
+    fn texture_coords_default_v_to_zero() {
+        let mesh = parse("vt 0.25\nvt 0.5 0.75\nvt 0.5 0.75 0.0\n");
+        let uvs: Vec<(f32, f32)> = mesh.uvs.iter().map(|uv| (uv.x, uv.y)).collect();
+        assert_eq!(uvs, vec![(0.25, 1.0), (0.5, 0.25), (0.5, 0.25)]);
+    }
+
+    #[test]
+    fn texture_coords_need_a_value() {
+        let mut mesh = parse("");
+        assert!(parse_obj("vt\n".as_bytes(), &mut mesh).is_err());
+    }
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn quads_are_split_into_triangles() {
+        let mesh = parse(&format!("{}f 1 2 3 4\n", SQUARE));
+        let triangles: Vec<[usize; 3]> = mesh.triangles.iter().map(|t| t.positions).collect();
+        assert_eq!(triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn polygons_are_split_into_fans() {
+        let mesh = parse("v 0 0 0\nv 2 0 0\nv 3 1 0\nv 1 2 0\nv -1 1 0\nf 1 2 3 4 5\n");
+        let triangles: Vec<[usize; 3]> = mesh.triangles.iter().map(|t| t.positions).collect();
+        assert_eq!(triangles, vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+        assert!(mesh
+            .triangles
+            .iter()
+            .all(|t| t.normals.is_none() && t.uvs.is_none()));
+    }
+
+    #[test]
+    fn faces_with_normals_and_no_texture_coords() {
+        let mesh = parse(&format!(
+            "{}vn 0 0 2\nvn 0 0 -1\nf 1//2 2//1 -2//1\n",
+            SQUARE
+        ));
+        let triangle = mesh.triangles[0];
+        assert_eq!(triangle.positions, [0, 1, 2]);
+        assert_eq!(triangle.normals, Some([1, 0, 0]));
+        assert!(triangle.uvs.is_none());
+        assert_eq!(mesh.normals[0].z, 1.0);
+    }
+
+    #[test]
+    fn faces_with_all_indices() {
+        let mesh = parse(&format!(
+            "{}vt 0 0\nvn 0 0 1\nf 1/1/1 2/1/1 3/1/1\n",
+            SQUARE
+        ));
+        let triangle = mesh.triangles[0];
+        assert_eq!(triangle.uvs, Some([0, 0, 0]));
+        assert_eq!(triangle.normals, Some([0, 0, 0]));
+    }
+
+    #[test]
+    fn faces_out_of_range_are_rejected() {
+        let mut mesh = parse(SQUARE);
+        assert!(parse_obj("f 1 2 5\n".as_bytes(), &mut mesh).is_err());
+        assert!(parse_obj("f 1 2\n".as_bytes(), &mut mesh).is_err());
+        assert!(parse_obj("f 1//1 2//1 3//1\n".as_bytes(), &mut mesh).is_err());
+    }
+}
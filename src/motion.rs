@@ -0,0 +1,51 @@
+use crate::vector::{Affine, Transform, Vector3};
+
+/// Movement of an element while the shutter is open, applied on top of its
+/// own transform. Times are in the same units as the camera's shutter
+/// interval.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Motion {
+    /// Moves by `velocity` per unit of time, starting from where the element
+    /// is at time 0.
+    Linear { velocity: Vector3 },
+    /// Goes from `start` at `start_time` to `end` at `end_time`, and holds
+    /// still outside of that interval.
+    Transforms {
+        start: Box<Transform>,
+        end: Box<Transform>,
+        #[serde(default)]
+        start_time: f64,
+        #[serde(default = "default_end_time")]
+        end_time: f64,
+    },
+}
+
+fn default_end_time() -> f64 {
+    1.0
+}
+
+impl Motion {
+    /// `transform`, if there is one, followed by the motion at `time`.
+    pub fn transform_at(&self, transform: Option<&Affine>, time: f64) -> Affine {
+        let own = transform.copied().unwrap_or_else(Affine::identity);
+        match *self {
+            // Only shifts the translation, which needs no new inverse.
+            Motion::Linear { velocity } => own.translated(&(velocity * time)),
+            Motion::Transforms {
+                ref start,
+                ref end,
+                start_time,
+                end_time,
+            } => {
+                let t = if end_time > start_time {
+                    ((time - start_time) / (end_time - start_time)).clamp(0.0, 1.0)
+                } else if time < end_time {
+                    0.0
+                } else {
+                    1.0
+                };
+                own.then(&start.lerp(end, t))
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use image::{codecs::hdr::HdrEncoder, Rgb};
+
+use crate::color::Color;
+
+/// Floating point image formats that keep the full range of `render_hdr`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HdrFormat {
+    OpenExr,
+    Radiance,
+    Pfm,
+}
+impl HdrFormat {
+    /// Picks the format from the file extension, if it is a HDR one.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "exr" => Some(HdrFormat::OpenExr),
+            "hdr" => Some(HdrFormat::Radiance),
+            "pfm" => Some(HdrFormat::Pfm),
+            _ => None,
+        }
+    }
+}
+
+/// Writes linear colors (row-major, top row first) to `path`.
+pub fn save_hdr(
+    path: &Path,
+    format: HdrFormat,
+    colors: &[Color],
+    width: u32,
+    height: u32,
+) -> Result<(), String> {
+    match format {
+        HdrFormat::OpenExr => save_exr(path, colors, width, height),
+        HdrFormat::Radiance => save_radiance(path, colors, width, height),
+        HdrFormat::Pfm => save_pfm(path, colors, width, height).map_err(|e| e.to_string()),
+    }
+}
+
+fn save_exr(path: &Path, colors: &[Color], width: u32, height: u32) -> Result<(), String> {
+    let width = width as usize;
+    exr::prelude::write_rgb_file(path, width, height as usize, |x, y| {
+        let c = colors[y * width + x];
+        (c.red, c.green, c.blue)
+    })
+    .map_err(|e| e.to_string())
+}
+
+fn save_radiance(path: &Path, colors: &[Color], width: u32, height: u32) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let pixels: Vec<Rgb<f32>> = colors
+        .iter()
+        // RGBE can't store negative values.
+        .map(|c| Rgb([c.red.max(0.0), c.green.max(0.0), c.blue.max(0.0)]))
+        .collect();
+    HdrEncoder::new(BufWriter::new(file))
+        .encode(&pixels, width as usize, height as usize)
+        .map_err(|e| e.to_string())
+}
+
+/// Portable float map: a short text header followed by little-endian floats,
+/// stored bottom row first.
+fn save_pfm(path: &Path, colors: &[Color], width: u32, height: u32) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    // A negative scale marks the data as little-endian.
+    write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+    for row in colors.chunks(width as usize).rev() {
+        for c in row {
+            writer.write_all(&c.red.to_le_bytes())?;
+            writer.write_all(&c.green.to_le_bytes())?;
+            writer.write_all(&c.blue.to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
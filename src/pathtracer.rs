@@ -0,0 +1,307 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::{
+    brdf::Brdf,
+    color::{Color, BLACK, WHITE},
+    element::Intersection,
+    environment::Environment,
+    material::SurfaceType,
+    point::Point,
+    rendering::{fresnel, shade_diffuse, Ray},
+    scene::Scene,
+    vector::Vector3,
+};
+
+/// Bounces after which paths may be terminated by Russian roulette.
+const ROULETTE_DEPTH: u32 = 3;
+/// Relative distance by which shadow rays may fall short of an emitter and
+/// still count as reaching it.
+const SHADOW_EPSILON: f64 = 1e-4;
+/// Lowest survival probability, so dim paths still have a chance to continue.
+const MIN_SURVIVAL: f32 = 0.05;
+
+enum Bounce {
+    /// Sampled from the surface's `Brdf`.
+    Scatter,
+    Mirror,
+    Transmit {
+        index: f32,
+    },
+}
+
+/// Unbiased Monte Carlo estimate of the light arriving along `ray`.
+///
+/// Diffuse and glossy bounces sample the surface's `Brdf` and use next-event
+/// estimation against `Scene::lights`. Area lights hit after such a bounce
+/// are therefore not counted again; only camera rays and specular paths see
+/// their emission. Emissive elements and the environment are both sampled
+/// directly and hit by BRDF samples, and the two estimates are combined with
+/// multiple importance sampling. Mirror and dielectric surfaces pick one of
+/// their lobes at random, in proportion to its weight.
+pub fn trace_path<R: Rng>(scene: Arc<Scene>, ray: &Ray, rng: &mut R) -> Color {
+    let mut radiance = BLACK;
+    let mut throughput = WHITE;
+    let mut ray = Ray {
+        origin: ray.origin,
+        direction: ray.direction,
+        time: ray.time,
+    };
+
+    let mut specular_bounce = true;
+    // Density of the BRDF sample that produced `ray`.
+    let mut bounce_pdf = 0.0;
+
+    for depth in 0..scene.max_recursion_depth {
+        let intersection = scene.trace(&ray);
+        if let Some((distance, light)) = scene.trace_lights(&ray) {
+            if intersection.as_ref().is_none_or(|i| distance < i.distance) {
+                if specular_bounce {
+                    radiance = radiance + throughput * light.emission();
+                }
+                break;
+            }
+        }
+        let intersection = match intersection {
+            Some(i) => i,
+            None => {
+                if let Some(ref environment) = scene.environment {
+                    let weight = if specular_bounce {
+                        1.0
+                    } else {
+                        power_heuristic(bounce_pdf, environment.pdf(&ray.direction))
+                    };
+                    radiance =
+                        radiance + throughput * environment.radiance(&ray.direction) * weight;
+                }
+                break;
+            }
+        };
+        let element = intersection.element;
+        let hit_point = ray.origin + (ray.direction * intersection.distance);
+        let detail = &intersection.detail;
+        let surface_normal = element.surface_normal_at(&hit_point, detail, ray.time);
+        let facing_normal = if ray.direction.dot(&surface_normal) > 0.0 {
+            -surface_normal
+        } else {
+            surface_normal
+        };
+        let texture_coords = element.texture_coords_at(&hit_point, detail, ray.time);
+        let surface_color = element.color(&texture_coords);
+
+        if element.material().emission.is_some() {
+            let weight = if specular_bounce {
+                1.0
+            } else {
+                let light_pdf = emitter_pdf(&scene, &intersection, &ray);
+                power_heuristic(bounce_pdf, light_pdf)
+            };
+            radiance =
+                radiance + throughput * element.material().emission(&texture_coords) * weight;
+        }
+
+        let bounce = match element.material().surface {
+            SurfaceType::Diffuse | SurfaceType::Principled { .. } => Bounce::Scatter,
+            SurfaceType::Reflective { reflectivity } => {
+                if rng.gen::<f32>() < reflectivity {
+                    Bounce::Mirror
+                } else {
+                    Bounce::Scatter
+                }
+            }
+            SurfaceType::Refractive {
+                index,
+                transparency,
+            } => {
+                if rng.gen::<f32>() >= transparency {
+                    Bounce::Scatter
+                } else {
+                    throughput = throughput * surface_color;
+                    if rng.gen::<f32>() < fresnel(ray.direction, surface_normal, index) {
+                        Bounce::Mirror
+                    } else {
+                        Bounce::Transmit { index }
+                    }
+                }
+            }
+        };
+
+        specular_bounce = !matches!(bounce, Bounce::Scatter);
+        ray = match bounce {
+            Bounce::Scatter => {
+                let brdf = Brdf::at(element, &texture_coords);
+                let view = -ray.direction;
+                let mut direct = shade_diffuse(
+                    scene.clone(),
+                    &intersection,
+                    hit_point,
+                    facing_normal,
+                    &ray,
+                    rng,
+                );
+                direct =
+                    direct + sample_emitters(&scene, &brdf, hit_point, facing_normal, &ray, rng);
+                if let Some(ref environment) = scene.environment {
+                    direct = direct
+                        + sample_environment(
+                            &scene,
+                            environment,
+                            &brdf,
+                            hit_point,
+                            facing_normal,
+                            &ray,
+                            rng,
+                        );
+                }
+                radiance = radiance + throughput * direct;
+
+                let sample = match brdf.sample(&facing_normal, &view, rng) {
+                    Some(sample) => sample,
+                    None => break,
+                };
+                throughput = throughput * sample.weight;
+                bounce_pdf = sample.pdf;
+                Ray {
+                    origin: hit_point + (facing_normal * scene.shadow_bias),
+                    direction: sample.direction,
+                    time: ray.time,
+                }
+            }
+            Bounce::Mirror => {
+                Ray::create_reflection(facing_normal, &ray, hit_point, scene.shadow_bias)
+            }
+            Bounce::Transmit { index } => {
+                Ray::create_transmission(surface_normal, &ray, hit_point, scene.shadow_bias, index)
+                    .unwrap_or_else(|| {
+                        Ray::create_reflection(facing_normal, &ray, hit_point, scene.shadow_bias)
+                    })
+            }
+        };
+
+        if depth >= ROULETTE_DEPTH {
+            let survival = throughput.max_component().clamp(MIN_SURVIVAL, 1.0);
+            if rng.gen::<f32>() >= survival {
+                break;
+            }
+            throughput = throughput * survival.recip();
+        }
+    }
+
+    radiance
+}
+
+/// Light reflected back along `ray` from one sampled direction of the
+/// environment, weighted against finding it with a BRDF sample.
+fn sample_environment<R: Rng>(
+    scene: &Scene,
+    environment: &Environment,
+    brdf: &Brdf,
+    hit_point: Point,
+    normal: Vector3,
+    ray: &Ray,
+    rng: &mut R,
+) -> Color {
+    let view = -ray.direction;
+    let sample = environment.sample(rng.gen(), rng.gen());
+    let cos_surface = normal.dot(&sample.direction);
+    if cos_surface <= 0.0 || sample.pdf <= 0.0 {
+        return BLACK;
+    }
+
+    let shadow_ray = Ray {
+        origin: hit_point + (normal * scene.shadow_bias),
+        direction: sample.direction,
+        time: ray.time,
+    };
+    if scene.trace(&shadow_ray).is_some() {
+        return BLACK;
+    }
+
+    let weight = power_heuristic(sample.pdf, brdf.pdf(&normal, &view, &sample.direction));
+    brdf.eval(&normal, &view, &sample.direction)
+        * sample.radiance
+        * (cos_surface / sample.pdf * weight as f64) as f32
+}
+
+/// Light reflected back along `ray` from a point picked on one of the emissive
+/// elements, weighted against hitting it with a BRDF sample.
+fn sample_emitters<R: Rng>(
+    scene: &Scene,
+    brdf: &Brdf,
+    hit_point: Point,
+    normal: Vector3,
+    ray: &Ray,
+    rng: &mut R,
+) -> Color {
+    let view = -ray.direction;
+    if scene.emitters.is_empty() {
+        return BLACK;
+    }
+    let count = scene.emitters.len();
+    let index = ((rng.gen::<f64>() * count as f64) as usize).min(count - 1);
+    let emitter = &scene.elements[scene.emitters[index]];
+    let sample = match emitter.sample_surface(rng.gen(), rng.gen(), ray.time) {
+        Some(sample) => sample,
+        None => return BLACK,
+    };
+
+    let to_light = sample.point - hit_point;
+    let distance = to_light.length();
+    let direction = to_light * distance.recip();
+    let cos_surface = normal.dot(&direction);
+    let cos_light = sample.normal.dot(&direction).abs();
+    if cos_surface <= 0.0 || cos_light <= 0.0 {
+        return BLACK;
+    }
+
+    let shadow_ray = Ray {
+        origin: hit_point + (normal * scene.shadow_bias),
+        direction,
+        time: ray.time,
+    };
+    if let Some(occluder) = scene.trace(&shadow_ray) {
+        if occluder.distance < distance * (1.0 - SHADOW_EPSILON) {
+            return BLACK;
+        }
+    }
+
+    // Converts the area density of the sample to solid angle.
+    let light_pdf = sample.pdf / count as f64 * distance * distance / cos_light;
+    let weight = power_heuristic(light_pdf, brdf.pdf(&normal, &view, &direction));
+    let texture_coords = emitter.texture_coords_at(&sample.point, &sample.detail, ray.time);
+    let emitted = emitter.material().emission(&texture_coords);
+    brdf.eval(&normal, &view, &direction)
+        * emitted
+        * (cos_surface / light_pdf * weight as f64) as f32
+}
+
+/// Density, per unit solid angle seen from `ray.origin`, with which
+/// `sample_emitters` picks the point where `ray` meets `intersection`.
+fn emitter_pdf(scene: &Scene, intersection: &Intersection, ray: &Ray) -> f64 {
+    if scene.emitters.is_empty() {
+        return 0.0;
+    }
+    let (element, distance) = (intersection.element, intersection.distance);
+    let point = ray.origin + (ray.direction * distance);
+    let cos_light = element
+        .surface_normal_at(&point, &intersection.detail, ray.time)
+        .dot(&ray.direction)
+        .abs();
+    if cos_light <= 0.0 {
+        return 0.0;
+    }
+    let area_pdf = element.surface_pdf(&point, &intersection.detail, ray.time);
+    area_pdf / scene.emitters.len() as f64 * distance * distance / cos_light
+}
+
+/// Weight of a sample drawn with density `pdf` when another strategy could
+/// have produced it with density `other_pdf`.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        0.0
+    } else {
+        (a / (a + b)) as f32
+    }
+}
@@ -1,81 +1,317 @@
-use std::sync::Arc;
+use std::{f64::consts::PI, sync::Arc};
+
+use rand::Rng;
 
 use crate::{
-    color::Color,
-    element::{Element, Intersection, Plane, Sphere},
+    brdf::{specular_color, Brdf},
+    camera::{CubeFace, FisheyeMapping, Projection, View},
+    color::{Color, BLACK},
+    element::{AxisAlignedBox, Cone, Cylinder, Disk, Element, Intersection, Plane, Sphere, Torus},
+    heightfield::Heightfield,
     material::SurfaceType,
+    mesh::{Mesh, TriangleHit},
     point::Point,
+    sampling::{stratified_samples, tangent_frame},
     scene::Scene,
+    sdf::Sdf,
     vector::Vector3,
 };
 
-const BLACK: Color = Color {
-    red: 0.0,
-    green: 0.0,
-    blue: 0.0,
-};
-
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector3,
+    /// When the ray travels, within the camera's shutter interval; moving
+    /// elements are intersected where they are at that time.
+    pub time: f64,
 }
 impl Ray {
-    pub fn create_prime(x: u64, y: u64, scene: Arc<Scene>) -> Self {
-        let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
-        let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let sensor_x =
-            ((((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio) * fov_adjustment;
-        let sendor_y = (1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0) * fov_adjustment;
+    /// Creates the camera ray through the image plane position (`x`, `y`),
+    /// in pixels from the top-left corner, leaving the camera at `lens` as
+    /// returned by `Camera::sample_lens` at `time`. Returns `None` for
+    /// positions the projection doesn't cover.
+    pub fn create_prime(
+        x: f64,
+        y: f64,
+        lens: (f64, f64),
+        time: f64,
+        view: &View,
+        scene: Arc<Scene>,
+    ) -> Option<Self> {
+        let camera = &scene.camera;
+        // Cubemap faces are each seen like a separate camera, down to the
+        // eyes and the lens.
+        let basis = match camera.projection {
+            Projection::Cubemap => view.face.unwrap_or(CubeFace::Front).basis(&camera.basis()),
+            _ => camera.basis(),
+        };
+        let (x, y) = (x - view.left as f64, y - view.top as f64);
+        let (width, height) = (view.width as f64, view.height as f64);
+        let aspect_ratio = width / height;
 
-        Self {
-            origin: Point::zero(),
-            direction: Vector3 {
-                x: sensor_x,
-                y: sendor_y,
-                z: -1.0,
+        // `direction` is scaled so that the focus distance along it lands
+        // on the plane (or, for panoramas, the sphere) in focus.
+        let (origin, direction) = match camera.projection {
+            Projection::Perspective => {
+                let fov = camera.fov.unwrap_or(scene.fov);
+                let fov_adjustment = (fov.to_radians() / 2.0).tan();
+                let sensor_x = (((x / width) * 2.0 - 1.0) * aspect_ratio) * fov_adjustment;
+                let sensor_y = (1.0 - (y / height) * 2.0) * fov_adjustment;
+                (
+                    camera.position,
+                    basis.right * sensor_x + basis.up * sensor_y + basis.forward,
+                )
+            }
+            Projection::Orthographic { view_width } => {
+                let sensor_x = ((x / width) - 0.5) * view_width;
+                let sensor_y = (0.5 - (y / height)) * view_width / aspect_ratio;
+                (
+                    camera.position + basis.right * sensor_x + basis.up * sensor_y,
+                    basis.forward,
+                )
+            }
+            Projection::Fisheye { mapping, fov } => {
+                let half_side = width.min(height) / 2.0;
+                let sensor_x = (x - width / 2.0) / half_side;
+                let sensor_y = (height / 2.0 - y) / half_side;
+                let radius = (sensor_x * sensor_x + sensor_y * sensor_y).sqrt();
+                if radius > 1.0 {
+                    return None;
+                }
+                let max_angle = fov.to_radians() / 2.0;
+                let angle = match mapping {
+                    FisheyeMapping::Equidistant => radius * max_angle,
+                    FisheyeMapping::Equisolid => {
+                        2.0 * (radius * (max_angle / 2.0).sin()).clamp(-1.0, 1.0).asin()
+                    }
+                };
+                let around = sensor_y.atan2(sensor_x);
+                (
+                    camera.position,
+                    basis.forward * angle.cos()
+                        + (basis.right * around.cos() + basis.up * around.sin()) * angle.sin(),
+                )
+            }
+            Projection::Equirectangular => {
+                let longitude = (x / width - 0.5) * 2.0 * PI;
+                let latitude = (0.5 - y / height) * PI;
+                (
+                    camera.position,
+                    (basis.forward * longitude.cos() + basis.right * longitude.sin())
+                        * latitude.cos()
+                        + basis.up * latitude.sin(),
+                )
+            }
+            Projection::Cubemap => {
+                let sensor_x = (x / width) * 2.0 - 1.0;
+                let sensor_y = 1.0 - (y / height) * 2.0;
+                (
+                    camera.position,
+                    basis.right * sensor_x + basis.up * sensor_y + basis.forward,
+                )
             }
-            .normalize(),
+        };
+
+        // Each eye's rays are sheared towards the other so that both meet
+        // again at the convergence distance in front of the camera.
+        let (origin, direction) = match view.eye {
+            Some(eye) => {
+                let offset = camera.eye_offset(eye);
+                let shear = offset * direction.dot(&basis.forward) / camera.convergence();
+                (
+                    origin + basis.right * offset,
+                    direction - basis.right * shear,
+                )
+            }
+            None => (origin, direction),
+        };
+
+        if lens == (0.0, 0.0) {
+            return Some(Self {
+                origin,
+                direction: direction.normalize(),
+                time,
+            });
         }
+
+        // Rays from anywhere on the lens meet again where they're in focus.
+        let focus = origin + direction * camera.focus_distance();
+        let origin = origin + basis.right * lens.0 + basis.up * lens.1;
+        Some(Self {
+            origin,
+            direction: (focus - origin).normalize(),
+            time,
+        })
     }
 
     pub fn create_reflection(
         normal: Vector3,
-        incident: Vector3,
+        incident: &Ray,
         intersection: Point,
         bias: f64,
     ) -> Self {
+        let direction = incident.direction;
         Self {
             origin: intersection + (normal * bias),
-            direction: incident - (2.0 * incident.dot(&normal) * normal),
+            direction: direction - (2.0 * direction.dot(&normal) * normal),
+            time: incident.time,
+        }
+    }
+
+    /// Refracts `incident` through the surface following Snell's law.
+    /// Returns `None` on total internal reflection.
+    pub fn create_transmission(
+        normal: Vector3,
+        incident: &Ray,
+        intersection: Point,
+        bias: f64,
+        index: f32,
+    ) -> Option<Self> {
+        let (time, incident) = (incident.time, incident.direction);
+        let mut ref_n = normal;
+        let mut eta_t = index as f64;
+        let mut eta_i = 1.0;
+        let mut i_dot_n = incident.dot(&normal);
+        if i_dot_n < 0.0 {
+            // Outside the surface
+            i_dot_n = -i_dot_n;
+        } else {
+            // Inside the surface; invert the normal and swap the indices of refraction
+            ref_n = -normal;
+            eta_i = eta_t;
+            eta_t = 1.0;
+        }
+
+        let eta = eta_i / eta_t;
+        let k = 1.0 - (eta * eta) * (1.0 - i_dot_n * i_dot_n);
+        if k < 0.0 {
+            None
+        } else {
+            Some(Self {
+                origin: intersection + (ref_n * -bias),
+                direction: ((incident + i_dot_n * ref_n) * eta - ref_n * k.sqrt()).normalize(),
+                time,
+            })
         }
     }
 }
 
 pub trait Intersectable {
     fn intersect(&self, ray: &Ray) -> Option<f64>;
-    fn surface_normal(&self, hit_point: &Point) -> Vector3;
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords;
+
+    /// Like `intersect`, but also reports which part of the surface was hit,
+    /// for shapes whose normal can't be told from the hit point alone.
+    fn hit(&self, ray: &Ray) -> Option<(f64, HitDetail)> {
+        self.intersect(ray)
+            .map(|distance| (distance, HitDetail::default()))
+    }
+
+    fn surface_normal(&self, hit_point: &Point, detail: &HitDetail) -> Vector3;
+    fn texture_coords(&self, hit_point: &Point, detail: &HitDetail) -> TextureCoords;
+}
+
+/// What `Intersectable::hit` found out about a hit besides its distance.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HitDetail {
+    /// Leaf of a CSG tree the hit is on, counting leaves from the left.
+    pub part: usize,
+    /// Whether the normal of that leaf points into the combined solid, as on
+    /// surfaces carved out by a difference.
+    pub flipped: bool,
+    /// For meshes, the triangle hit and where on it.
+    pub triangle: Option<TriangleHit>,
 }
 
+/// Transformed elements are intersected by moving the ray into object space,
+/// so the shapes themselves never need to know about transforms. Moving
+/// elements are placed where they are at the ray's time first.
 impl Intersectable for Element {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.hit(ray).map(|(distance, _)| distance)
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<(f64, HitDetail)> {
+        match self.transform_at(ray.time) {
+            Some(t) => {
+                let direction = t.vector_to_object(&ray.direction);
+                let scale = direction.length();
+                let local_ray = Ray {
+                    origin: t.point_to_object(&ray.origin),
+                    direction: direction * scale.recip(),
+                    time: ray.time,
+                };
+                // Distances along the normalized object-space ray are
+                // `scale` times longer than in world space.
+                self.hit_object(&local_ray)
+                    .map(|(d, detail)| (d / scale, detail))
+            }
+            None => self.hit_object(ray),
+        }
+    }
+
+    /// With the element at rest, as CSG parts and shared geometries always
+    /// are; see `Element::surface_normal_at` for moving elements.
+    fn surface_normal(&self, hit_point: &Point, detail: &HitDetail) -> Vector3 {
+        self.placed_normal(self.transform().map(|t| &t.affine), hit_point, detail)
+    }
+
+    fn texture_coords(&self, hit_point: &Point, detail: &HitDetail) -> TextureCoords {
+        self.placed_texture_coords(self.transform().map(|t| &t.affine), hit_point, detail)
+    }
+}
+
+impl Element {
+    fn hit_object(&self, ray: &Ray) -> Option<(f64, HitDetail)> {
         match *self {
-            Element::Sphere(ref s) => s.intersect(ray),
-            Element::Plane(ref p) => p.intersect(ray),
+            Element::Sphere(ref s) => s.hit(ray),
+            Element::Plane(ref p) => p.hit(ray),
+            Element::Mesh(ref m) => m.hit(ray),
+            Element::Instance(ref i) => i.geometry().hit(ray),
+            Element::Box(ref b) => b.hit(ray),
+            Element::Cylinder(ref c) => c.hit(ray),
+            Element::Cone(ref c) => c.hit(ray),
+            Element::Disk(ref d) => d.hit(ray),
+            Element::Torus(ref t) => t.hit(ray),
+            Element::Csg(ref c) => c.hit(ray),
+            Element::Sdf(ref s) => s.hit(ray),
+            Element::Heightfield(ref h) => h.hit(ray),
         }
     }
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+    pub(crate) fn object_normal(&self, hit_point: &Point, detail: &HitDetail) -> Vector3 {
         match *self {
-            Element::Sphere(ref s) => s.surface_normal(hit_point),
-            Element::Plane(ref p) => p.surface_normal(hit_point),
+            Element::Sphere(ref s) => s.surface_normal(hit_point, detail),
+            Element::Plane(ref p) => p.surface_normal(hit_point, detail),
+            Element::Mesh(ref m) => m.surface_normal(hit_point, detail),
+            Element::Instance(ref i) => i.geometry().surface_normal(hit_point, detail),
+            Element::Box(ref b) => b.surface_normal(hit_point, detail),
+            Element::Cylinder(ref c) => c.surface_normal(hit_point, detail),
+            Element::Cone(ref c) => c.surface_normal(hit_point, detail),
+            Element::Disk(ref d) => d.surface_normal(hit_point, detail),
+            Element::Torus(ref t) => t.surface_normal(hit_point, detail),
+            Element::Csg(ref c) => c.surface_normal(hit_point, detail),
+            Element::Sdf(ref s) => s.surface_normal(hit_point, detail),
+            Element::Heightfield(ref h) => h.surface_normal(hit_point, detail),
         }
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+    pub(crate) fn object_texture_coords(
+        &self,
+        hit_point: &Point,
+        detail: &HitDetail,
+    ) -> TextureCoords {
         match *self {
-            Element::Sphere(ref s) => s.texture_coords(hit_point),
-            Element::Plane(ref p) => p.texture_coords(hit_point),
+            Element::Sphere(ref s) => s.texture_coords(hit_point, detail),
+            Element::Plane(ref p) => p.texture_coords(hit_point, detail),
+            Element::Mesh(ref m) => m.texture_coords(hit_point, detail),
+            Element::Instance(ref i) => i.geometry().texture_coords(hit_point, detail),
+            Element::Box(ref b) => b.texture_coords(hit_point, detail),
+            Element::Cylinder(ref c) => c.texture_coords(hit_point, detail),
+            Element::Cone(ref c) => c.texture_coords(hit_point, detail),
+            Element::Disk(ref d) => d.texture_coords(hit_point, detail),
+            Element::Torus(ref t) => t.texture_coords(hit_point, detail),
+            Element::Csg(ref c) => c.texture_coords(hit_point, detail),
+            Element::Sdf(ref s) => s.texture_coords(hit_point, detail),
+            Element::Heightfield(ref h) => h.texture_coords(hit_point, detail),
         }
     }
 }
@@ -97,15 +333,22 @@ impl Intersectable for Sphere {
             return None;
         }
 
-        let distance = if t0 < t1 { t0 } else { t1 };
+        // Rays starting inside the sphere (e.g. refracted rays) exit at t1.
+        let distance = if t0 < 0.0 {
+            t1
+        } else if t1 < 0.0 {
+            t0
+        } else {
+            t0.min(t1)
+        };
         Some(distance)
     }
 
-    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+    fn surface_normal(&self, hit_point: &Point, _: &HitDetail) -> Vector3 {
         (*hit_point - self.center).normalize()
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+    fn texture_coords(&self, hit_point: &Point, _: &HitDetail) -> TextureCoords {
         let hit_vec = *hit_point - self.center;
         TextureCoords {
             x: (1.0 + (hit_vec.z.atan2(hit_vec.x) as f32) / std::f32::consts::PI) * 0.5,
@@ -120,7 +363,7 @@ impl Intersectable for Plane {
         let denom = normal.dot(&ray.direction);
         if denom > 1e-6 {
             let v = self.origin - ray.origin;
-            let distance = v.dot(&normal) / denom;
+            let distance = v.dot(normal) / denom;
             if distance >= 0.0 {
                 return Some(distance);
             }
@@ -128,11 +371,11 @@ impl Intersectable for Plane {
         None
     }
 
-    fn surface_normal(&self, _: &Point) -> Vector3 {
+    fn surface_normal(&self, _: &Point, _: &HitDetail) -> Vector3 {
         -self.normal
     }
 
-    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+    fn texture_coords(&self, hit_point: &Point, _: &HitDetail) -> TextureCoords {
         let mut x_axis = self.normal.cross(&Vector3 {
             x: 0.0,
             y: 0.0,
@@ -155,70 +398,925 @@ impl Intersectable for Plane {
     }
 }
 
+impl Intersectable for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.hit(ray).map(|(distance, _)| distance)
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<(f64, HitDetail)> {
+        self.bvh
+            .nearest(ray, |i| {
+                intersect_triangle(self.vertices(&self.triangles[i]), ray)
+                    .map(|(distance, u, v)| (distance, TriangleHit { triangle: i, u, v }))
+            })
+            .map(|(_, distance, hit)| {
+                let detail = HitDetail {
+                    triangle: Some(hit),
+                    ..HitDetail::default()
+                };
+                (distance, detail)
+            })
+    }
+
+    fn surface_normal(&self, _: &Point, detail: &HitDetail) -> Vector3 {
+        detail
+            .triangle
+            .map_or_else(Vector3::zero, |hit| self.normal_at(&hit))
+    }
+
+    fn texture_coords(&self, _: &Point, detail: &HitDetail) -> TextureCoords {
+        detail
+            .triangle
+            .map_or(TextureCoords { x: 0.0, y: 0.0 }, |hit| {
+                self.texture_coords_at(&hit)
+            })
+    }
+}
+
+impl Intersectable for Heightfield {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.crossings(ray, true).first().cloned()
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: &HitDetail) -> Vector3 {
+        self.normal_at(hit_point)
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: &HitDetail) -> TextureCoords {
+        self.texture_coords_at(hit_point)
+    }
+}
+
+impl Intersectable for AxisAlignedBox {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (near, far) = slabs(&self.min, &self.max, ray)?;
+        if near > 0.0 {
+            Some(near)
+        } else if far > 0.0 {
+            Some(far)
+        } else {
+            None
+        }
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: &HitDetail) -> Vector3 {
+        let (axis, sign) = self.face(hit_point);
+        let mut normal = [0.0; 3];
+        normal[axis] = sign;
+        Vector3 {
+            x: normal[0],
+            y: normal[1],
+            z: normal[2],
+        }
+    }
+
+    /// Each face is mapped to the whole [0, 1]² square.
+    fn texture_coords(&self, hit_point: &Point, _: &HitDetail) -> TextureCoords {
+        let (axis, _) = self.face(hit_point);
+        let relative = |i: usize| {
+            let (p, min, max) = (
+                coordinate(hit_point, i),
+                coordinate(&self.min, i),
+                coordinate(&self.max, i),
+            );
+            ((p - min) / (max - min)) as f32
+        };
+        let (u, v) = match axis {
+            0 => (2, 1),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+        TextureCoords {
+            x: relative(u),
+            y: 1.0 - relative(v),
+        }
+    }
+}
+
+impl AxisAlignedBox {
+    /// Axis and direction of the face `hit_point` lies on.
+    fn face(&self, hit_point: &Point) -> (usize, f64) {
+        let mut best = (0, 1.0);
+        let mut best_offset = f64::NEG_INFINITY;
+        for axis in 0..3 {
+            let (p, min, max) = (
+                coordinate(hit_point, axis),
+                coordinate(&self.min, axis),
+                coordinate(&self.max, axis),
+            );
+            let half = (max - min) / 2.0;
+            if half == 0.0 {
+                // A flat box is hit on its only face.
+                return (axis, if p < min { -1.0 } else { 1.0 });
+            }
+            // Relative distance from the center; 1 on the faces.
+            let offset = (p - (min + half)) / half;
+            if offset.abs() > best_offset {
+                best_offset = offset.abs();
+                best = (axis, offset.signum());
+            }
+        }
+        best
+    }
+}
+
+fn coordinate(p: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => p.x,
+        1 => p.y,
+        _ => p.z,
+    }
+}
+
+/// Distances at which `ray` enters and leaves the box from `min` to `max`.
+pub(crate) fn slabs(min: &Point, max: &Point, ray: &Ray) -> Option<(f64, f64)> {
+    let mut near = f64::NEG_INFINITY;
+    let mut far = f64::INFINITY;
+    let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+    let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+    let (min, max) = ([min.x, min.y, min.z], [max.x, max.y, max.z]);
+    for i in 0..3 {
+        let inv = direction[i].recip();
+        let mut t0 = (min[i] - origin[i]) * inv;
+        let mut t1 = (max[i] - origin[i]) * inv;
+        if t0 > t1 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+        near = near.max(t0);
+        far = far.min(t1);
+    }
+    if near <= far {
+        Some((near, far))
+    } else {
+        None
+    }
+}
+
+/// Closest positive root of `a t² + b t + c`, keeping only those `accept`
+/// agrees with.
+fn nearest_quadratic_root<F: Fn(f64) -> bool>(a: f64, b: f64, c: f64, accept: F) -> Option<f64> {
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return None;
+        }
+        let t = -c / b;
+        return if t > 0.0 && accept(t) { Some(t) } else { None };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrt = discriminant.sqrt();
+    let (mut t0, mut t1) = ((-b - sqrt) / (2.0 * a), (-b + sqrt) / (2.0 * a));
+    if t0 > t1 {
+        std::mem::swap(&mut t0, &mut t1);
+    }
+    [t0, t1].iter().cloned().find(|&t| t > 0.0 && accept(t))
+}
+
+/// Distance to the horizontal disk of `radius` around `center`.
+fn intersect_cap(center: &Point, radius: f64, ray: &Ray) -> Option<f64> {
+    if ray.direction.y.abs() < 1e-12 {
+        return None;
+    }
+    let t = (center.y - ray.origin.y) / ray.direction.y;
+    let p = ray.origin + ray.direction * t;
+    let (dx, dz) = (p.x - center.x, p.z - center.z);
+    if t > 0.0 && dx * dx + dz * dz <= radius * radius {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+fn nearest(candidates: &[Option<f64>]) -> Option<f64> {
+    candidates
+        .iter()
+        .flatten()
+        .cloned()
+        .min_by(|a, b| a.partial_cmp(b).unwrap())
+}
+
+/// Maps the angle around the Y axis and the height above the base to the
+/// unit square.
+fn cylindrical_coords(hit_vec: &Vector3, height: f64) -> TextureCoords {
+    TextureCoords {
+        x: (1.0 + (hit_vec.z.atan2(hit_vec.x) as f32) / std::f32::consts::PI) * 0.5,
+        y: 1.0 - (hit_vec.y / height).clamp(0.0, 1.0) as f32,
+    }
+}
+
+/// Whether `hit_vec` (relative to the center of a cap) lies on the cap
+/// rather than on the curved side, judged by which surface is closer.
+fn on_cap(hit_vec: &Vector3, cap_y: f64, side_distance: f64) -> bool {
+    (hit_vec.y - cap_y).abs() < side_distance
+}
+
+impl Intersectable for Cylinder {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+        let a = d.x * d.x + d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.z * d.z);
+        let c = o.x * o.x + o.z * o.z - self.radius * self.radius;
+        let side = nearest_quadratic_root(a, b, c, |t| {
+            let y = o.y + d.y * t;
+            (0.0..=self.height).contains(&y)
+        });
+        if !self.capped {
+            return side;
+        }
+
+        let top = self.center
+            + Vector3 {
+                x: 0.0,
+                y: self.height,
+                z: 0.0,
+            };
+        nearest(&[
+            side,
+            intersect_cap(&self.center, self.radius, ray),
+            intersect_cap(&top, self.radius, ray),
+        ])
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: &HitDetail) -> Vector3 {
+        let hit_vec = *hit_point - self.center;
+        let radial = (hit_vec.x * hit_vec.x + hit_vec.z * hit_vec.z).sqrt();
+        let side_distance = (radial - self.radius).abs();
+        if self.capped && on_cap(&hit_vec, 0.0, side_distance) {
+            return Vector3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            };
+        }
+        if self.capped && on_cap(&hit_vec, self.height, side_distance) {
+            return Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            };
+        }
+        Vector3 {
+            x: hit_vec.x,
+            y: 0.0,
+            z: hit_vec.z,
+        }
+        .normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: &HitDetail) -> TextureCoords {
+        cylindrical_coords(&(*hit_point - self.center), self.height)
+    }
+}
+
+impl Intersectable for Cone {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        // x² + z² = (k (h - y))², with the apex at y = h.
+        let o = ray.origin - self.center;
+        let d = ray.direction;
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let h = self.height - o.y;
+        let a = d.x * d.x + d.z * d.z - k2 * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z + k2 * h * d.y);
+        let c = o.x * o.x + o.z * o.z - k2 * h * h;
+        let side = nearest_quadratic_root(a, b, c, |t| {
+            let y = o.y + d.y * t;
+            (0.0..=self.height).contains(&y)
+        });
+        if !self.capped {
+            return side;
+        }
+        nearest(&[side, intersect_cap(&self.center, self.radius, ray)])
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: &HitDetail) -> Vector3 {
+        let hit_vec = *hit_point - self.center;
+        let radial = (hit_vec.x * hit_vec.x + hit_vec.z * hit_vec.z).sqrt();
+        let side_radius = self.radius * (1.0 - hit_vec.y / self.height);
+        if self.capped && on_cap(&hit_vec, 0.0, (radial - side_radius).abs()) {
+            return Vector3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            };
+        }
+        if radial == 0.0 {
+            // The apex.
+            return Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            };
+        }
+        Vector3 {
+            x: hit_vec.x / radial,
+            y: self.radius / self.height,
+            z: hit_vec.z / radial,
+        }
+        .normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point, _: &HitDetail) -> TextureCoords {
+        cylindrical_coords(&(*hit_point - self.center), self.height)
+    }
+}
+
+impl Intersectable for Disk {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let denom = self.normal.dot(&ray.direction);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let distance = (self.center - ray.origin).dot(&self.normal) / denom;
+        if distance <= 0.0 {
+            return None;
+        }
+        let offset = (ray.origin + ray.direction * distance) - self.center;
+        if offset.norm() <= self.radius * self.radius {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    fn surface_normal(&self, _: &Point, _: &HitDetail) -> Vector3 {
+        self.normal
+    }
+
+    /// The disk's bounding square mapped to [0, 1]².
+    fn texture_coords(&self, hit_point: &Point, _: &HitDetail) -> TextureCoords {
+        let (tangent, bitangent) = tangent_frame(&self.normal);
+        let hit_vec = *hit_point - self.center;
+        TextureCoords {
+            x: (0.5 + hit_vec.dot(&tangent) / (2.0 * self.radius)) as f32,
+            y: (0.5 - hit_vec.dot(&bitangent) / (2.0 * self.radius)) as f32,
+        }
+    }
+}
+
+impl Intersectable for Torus {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.crossings(ray)
+            .into_iter()
+            .filter(|&t| t > 0.0)
+            .min_by(|a, b| a.partial_cmp(b).unwrap())
+    }
+
+    fn surface_normal(&self, hit_point: &Point, _: &HitDetail) -> Vector3 {
+        let hit_vec = *hit_point - self.center;
+        let radial = (hit_vec.x * hit_vec.x + hit_vec.z * hit_vec.z).sqrt();
+        if radial == 0.0 {
+            return Vector3 {
+                x: 0.0,
+                y: hit_vec.y.signum(),
+                z: 0.0,
+            };
+        }
+        // Away from the closest point on the circle through the tube.
+        let scale = self.major_radius / radial;
+        Vector3 {
+            x: hit_vec.x * (1.0 - scale),
+            y: hit_vec.y,
+            z: hit_vec.z * (1.0 - scale),
+        }
+        .normalize()
+    }
+
+    /// Angle around the ring, then around the tube.
+    fn texture_coords(&self, hit_point: &Point, _: &HitDetail) -> TextureCoords {
+        let hit_vec = *hit_point - self.center;
+        let radial = (hit_vec.x * hit_vec.x + hit_vec.z * hit_vec.z).sqrt();
+        let tube_angle = hit_vec.y.atan2(radial - self.major_radius);
+        TextureCoords {
+            x: (1.0 + (hit_vec.z.atan2(hit_vec.x) as f32) / std::f32::consts::PI) * 0.5,
+            y: (1.0 + (tube_angle as f32) / std::f32::consts::PI) * 0.5,
+        }
+    }
+}
+
+impl Torus {
+    /// Distances, in no particular order and including negative ones, at
+    /// which the line through `ray` crosses the surface.
+    pub(crate) fn crossings(&self, ray: &Ray) -> Vec<f64> {
+        let (major, minor) = (self.major_radius, self.minor_radius);
+
+        // Start from the bounding sphere, which keeps the quartic's
+        // coefficients small and its roots accurate.
+        let to_center = self.center - ray.origin;
+        let bound = major + minor;
+        let adj = to_center.dot(&ray.direction);
+        let d2 = to_center.norm() - adj * adj;
+        if d2 > bound * bound {
+            return Vec::new();
+        }
+        let start = adj - (bound * bound - d2).sqrt();
+
+        let o = (ray.origin + ray.direction * start) - self.center;
+        let d = ray.direction;
+        let n = o.dot(&d);
+        let k = o.norm() + major * major - minor * minor;
+        let four_r2 = 4.0 * major * major;
+        let roots = solve_quartic(
+            4.0 * n,
+            4.0 * n * n + 2.0 * k - four_r2 * (d.x * d.x + d.z * d.z),
+            4.0 * n * k - 2.0 * four_r2 * (o.x * d.x + o.z * d.z),
+            k * k - four_r2 * (o.x * o.x + o.z * o.z),
+        );
+        roots.into_iter().map(|t| t + start).collect()
+    }
+}
+
+impl Intersectable for Sdf {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (start, end) = self.range(ray)?;
+        self.march(ray, start.max(0.0), end, true).first().cloned()
+    }
+
+    /// Gradient of the distance, by central differences.
+    fn surface_normal(&self, hit_point: &Point, _: &HitDetail) -> Vector3 {
+        let h = self.epsilon;
+        let difference = |offset: Vector3| {
+            self.distance(&(*hit_point + offset)) - self.distance(&(*hit_point + -offset))
+        };
+        Vector3 {
+            x: difference(Vector3 {
+                x: h,
+                y: 0.0,
+                z: 0.0,
+            }),
+            y: difference(Vector3 {
+                x: 0.0,
+                y: h,
+                z: 0.0,
+            }),
+            z: difference(Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: h,
+            }),
+        }
+        .normalize()
+    }
+
+    /// Spherical coordinates around the origin of the shape.
+    fn texture_coords(&self, hit_point: &Point, _: &HitDetail) -> TextureCoords {
+        let hit_vec = (*hit_point - Point::zero()).normalize();
+        TextureCoords {
+            x: (1.0 + (hit_vec.z.atan2(hit_vec.x) as f32) / std::f32::consts::PI) * 0.5,
+            y: hit_vec.y.clamp(-1.0, 1.0).acos() as f32 / std::f32::consts::PI,
+        }
+    }
+}
+
+/// Real roots of the monic cubic `x³ + a x² + b x + c`.
+fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    if r * r < q * q * q {
+        let theta = (r / (q * q * q).sqrt()).clamp(-1.0, 1.0).acos();
+        let scale = -2.0 * q.sqrt();
+        (0..3)
+            .map(|k| {
+                scale * ((theta + 2.0 * std::f64::consts::PI * k as f64) / 3.0).cos() - a / 3.0
+            })
+            .collect()
+    } else {
+        let s = -r.signum() * (r.abs() + (r * r - q * q * q).sqrt()).cbrt();
+        let t = if s == 0.0 { 0.0 } else { q / s };
+        vec![s + t - a / 3.0]
+    }
+}
+
+/// Real roots of the monic quartic `x⁴ + a x³ + b x² + c x + d`, using
+/// Ferrari's method followed by a few Newton steps to polish each root.
+fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // Depressed quartic y⁴ + p y² + q y + r with x = y - a/4.
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+    let mut roots = Vec::with_capacity(4);
+    let mut push_quadratic = |b: f64, c: f64| {
+        let discriminant = b * b - 4.0 * c;
+        if discriminant >= 0.0 {
+            let sqrt = discriminant.sqrt();
+            roots.push((-b - sqrt) / 2.0);
+            roots.push((-b + sqrt) / 2.0);
+        }
+    };
+
+    if q.abs() < 1e-12 {
+        // Biquadratic: solve for y².
+        let discriminant = p * p - 4.0 * r;
+        if discriminant >= 0.0 {
+            let sqrt = discriminant.sqrt();
+            for z in [(-p - sqrt) / 2.0, (-p + sqrt) / 2.0].iter() {
+                if *z >= 0.0 {
+                    push_quadratic(0.0, -z);
+                }
+            }
+        }
+    } else {
+        // Any positive root of the resolvent cubic splits the quartic into
+        // two quadratics.
+        let m = solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0)
+            .into_iter()
+            .fold(f64::NEG_INFINITY, f64::max);
+        if m > 0.0 {
+            let s = (2.0 * m).sqrt();
+            push_quadratic(-s, p / 2.0 + m + q / (2.0 * s));
+            push_quadratic(s, p / 2.0 + m - q / (2.0 * s));
+        }
+    }
+
+    roots
+        .into_iter()
+        .map(|y| {
+            let mut x = y - a / 4.0;
+            for _ in 0..2 {
+                let f = (((x + a) * x + b) * x + c) * x + d;
+                let df = ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+                if df.abs() > 1e-12 {
+                    x -= f / df;
+                }
+            }
+            x
+        })
+        .collect()
+}
+
+/// Möller–Trumbore ray/triangle intersection.
+fn intersect_triangle(vertices: (Point, Point, Point), ray: &Ray) -> Option<(f64, f64, f64)> {
+    triangle_crossing(vertices, ray).filter(|&(distance, _, _)| distance > 0.0)
+}
+
+/// Distance, possibly negative, at which the line through `ray` crosses the
+/// triangle, and the barycentric coordinates (u, v) of the crossing.
+pub(crate) fn triangle_crossing(
+    (p0, p1, p2): (Point, Point, Point),
+    ray: &Ray,
+) -> Option<(f64, f64, f64)> {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let p = ray.direction.cross(&e2);
+    let det = e1.dot(&p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let inv_det = det.recip();
+    let s = ray.origin - p0;
+    let u = s.dot(&p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(&e1);
+    let v = ray.direction.dot(&q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    Some((e2.dot(&q) * inv_det, u, v))
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct TextureCoords {
     pub x: f32,
     pub y: f32,
 }
 
-fn shade_diffuse(
+/// Light reflected back along `ray` at `hit_point` from every light in the
+/// scene that isn't shadowed, using the element's `Brdf`. Not clamped, so
+/// bright highlights keep their range until the final image conversion.
+///
+/// Area lights are averaged over several stratified shadow rays, which gives
+/// soft shadows.
+pub fn shade_diffuse<R: Rng>(
     scene: Arc<Scene>,
-    element: &Element,
+    intersection: &Intersection,
     hit_point: Point,
     surface_normal: Vector3,
+    ray: &Ray,
+    rng: &mut R,
 ) -> Color {
-    let texture_coords = element.texture_coords(&hit_point);
+    let element = intersection.element;
+    let texture_coords = element.texture_coords_at(&hit_point, &intersection.detail, ray.time);
+    let brdf = Brdf::at(element, &texture_coords);
+    let view = -ray.direction;
     let mut color = BLACK;
 
     for light in &scene.lights {
-        let direction_to_light = light.direction_from(&hit_point);
+        let sample_count = light.sample_count();
+        let mut reflected = BLACK;
+        for (u, v) in stratified_samples(sample_count, rng) {
+            let sample = light.sample(&hit_point, u, v);
+            let cos_surface = (surface_normal.dot(&sample.direction) as f32).max(0.0);
+            if cos_surface == 0.0 || sample.intensity == 0.0 {
+                continue;
+            }
 
-        let shadow_ray = Ray {
-            origin: hit_point + (surface_normal * scene.shadow_bias),
-            direction: direction_to_light,
-        };
-        let shadow_intersection = scene.trace(&shadow_ray);
-        let in_light = shadow_intersection.is_none()
-            || shadow_intersection.unwrap().distance > light.distance(&hit_point);
+            let shadow_ray = Ray {
+                origin: hit_point + (surface_normal * scene.shadow_bias),
+                direction: sample.direction,
+                time: ray.time,
+            };
+            let shadow_intersection = scene.trace(&shadow_ray);
+            let in_light = shadow_intersection.is_none()
+                || shadow_intersection.unwrap().distance > sample.distance;
 
-        let light_intensity = if in_light {
-            light.intensity(&hit_point)
-        } else {
-            0.0
-        };
-        let light_power =
-            (surface_normal.dot(&direction_to_light) as f32).max(0.0) * light_intensity;
-        let light_reflected = element.albedo() / std::f32::consts::PI;
-        let light_color = light.color() * light_power * light_reflected;
+            if in_light {
+                let brdf_value = brdf.eval(&surface_normal, &view, &sample.direction);
+                reflected = reflected + brdf_value * (cos_surface * sample.intensity);
+            }
+        }
 
-        color = color + (element.color(&texture_coords) * light_color);
+        color = color + light.color() * reflected * (sample_count as f32).recip();
     }
 
-    color.clamp()
+    color
 }
 
-fn get_color(scene: Arc<Scene>, ray: &Ray, intersection: &Intersection, depth: u32) -> Color {
+fn get_color<R: Rng>(
+    scene: Arc<Scene>,
+    ray: &Ray,
+    intersection: &Intersection,
+    depth: u32,
+    rng: &mut R,
+) -> Color {
     let hit_point = ray.origin + (ray.direction * intersection.distance);
-    let surface_normal = intersection.element.surface_normal(&hit_point);
+    let surface_normal =
+        intersection
+            .element
+            .surface_normal_at(&hit_point, &intersection.detail, ray.time);
 
     let nscene = scene.clone();
-    let mut color = shade_diffuse(nscene, intersection.element, hit_point, surface_normal);
-    if let SurfaceType::Reflective { reflectivity } = intersection.element.material().surface {
-        let reflection_ray =
-            Ray::create_reflection(surface_normal, ray.direction, hit_point, scene.shadow_bias);
-        color = color * (1.0 - reflectivity);
-        color = color + (cast_ray(scene, &reflection_ray, depth + 1) * reflectivity);
+    match intersection.element.material().surface {
+        SurfaceType::Diffuse => {
+            shade_diffuse(nscene, intersection, hit_point, surface_normal, ray, rng)
+        }
+        SurfaceType::Reflective { reflectivity } => {
+            let mut color =
+                shade_diffuse(nscene, intersection, hit_point, surface_normal, ray, rng);
+            let reflection_ray =
+                Ray::create_reflection(surface_normal, ray, hit_point, scene.shadow_bias);
+            color = color * (1.0 - reflectivity);
+            color = color + (cast_ray(scene, &reflection_ray, depth + 1, rng) * reflectivity);
+            color
+        }
+        SurfaceType::Refractive {
+            index,
+            transparency,
+        } => {
+            let diffuse_color =
+                shade_diffuse(nscene, intersection, hit_point, surface_normal, ray, rng);
+            let transmitted_color =
+                shade_transmission(scene, ray, intersection, surface_normal, index, depth, rng);
+            diffuse_color * (1.0 - transparency) + transmitted_color * transparency
+        }
+        SurfaceType::Principled { .. } => {
+            let direct = shade_diffuse(nscene, intersection, hit_point, surface_normal, ray, rng);
+            // Stand-in for the glossy lobe, which only a stochastic integrator
+            // can sample properly: a mirror reflection fading out with roughness.
+            let texture_coords =
+                intersection
+                    .element
+                    .texture_coords_at(&hit_point, &intersection.detail, ray.time);
+            let (specular, roughness) = match Brdf::at(intersection.element, &texture_coords) {
+                Brdf::Principled {
+                    base_color,
+                    metallic,
+                    roughness,
+                    specular,
+                } => (specular_color(base_color, metallic, specular), roughness),
+                Brdf::Lambert { .. } => (BLACK, 1.0),
+            };
+            let smoothness = (1.0 - roughness) * (1.0 - roughness);
+            if smoothness <= 0.0 {
+                return direct;
+            }
+            let reflection_ray =
+                Ray::create_reflection(surface_normal, ray, hit_point, scene.shadow_bias);
+            let reflected = cast_ray(scene, &reflection_ray, depth + 1, rng);
+            direct + reflected * specular * smoothness
+        }
     }
-    color
 }
 
-pub fn cast_ray(scene: Arc<Scene>, ray: &Ray, depth: u32) -> Color {
+/// Reflected and refracted light leaving a dielectric, weighted by Fresnel.
+fn shade_transmission<R: Rng>(
+    scene: Arc<Scene>,
+    ray: &Ray,
+    intersection: &Intersection,
+    surface_normal: Vector3,
+    index: f32,
+    depth: u32,
+    rng: &mut R,
+) -> Color {
+    let element = intersection.element;
+    let hit_point = ray.origin + (ray.direction * intersection.distance);
+    let texture_coords = element.texture_coords_at(&hit_point, &intersection.detail, ray.time);
+    let surface_color = element.color(&texture_coords);
+    let kr = fresnel(ray.direction, surface_normal, index);
+
+    let mut refraction_color = BLACK;
+    if kr < 1.0 {
+        if let Some(transmission_ray) =
+            Ray::create_transmission(surface_normal, ray, hit_point, scene.shadow_bias, index)
+        {
+            refraction_color = cast_ray(scene.clone(), &transmission_ray, depth + 1, rng);
+        }
+    }
+
+    // Reflect off the side the ray came from, which is the inside on the way out.
+    let facing_normal = if ray.direction.dot(&surface_normal) > 0.0 {
+        -surface_normal
+    } else {
+        surface_normal
+    };
+    let reflection_ray = Ray::create_reflection(facing_normal, ray, hit_point, scene.shadow_bias);
+    let reflection_color = cast_ray(scene, &reflection_ray, depth + 1, rng);
+
+    (reflection_color * kr + refraction_color * (1.0 - kr)) * surface_color
+}
+
+/// Fraction of light reflected by a dielectric surface (unpolarized Fresnel
+/// equations). Returns 1.0 on total internal reflection.
+pub fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f32 {
+    let i_dot_n = incident.dot(&normal);
+    let mut eta_i = 1.0;
+    let mut eta_t = index as f64;
+    if i_dot_n > 0.0 {
+        eta_i = eta_t;
+        eta_t = 1.0;
+    }
+
+    let sin_t = eta_i / eta_t * (1.0 - i_dot_n * i_dot_n).max(0.0).sqrt();
+    if sin_t > 1.0 {
+        // Total internal reflection
+        1.0
+    } else {
+        let cos_t = (1.0 - sin_t * sin_t).max(0.0).sqrt();
+        let cos_i = i_dot_n.abs();
+        let r_s = ((eta_t * cos_i) - (eta_i * cos_t)) / ((eta_t * cos_i) + (eta_i * cos_t));
+        let r_p = ((eta_i * cos_i) - (eta_t * cos_t)) / ((eta_i * cos_i) + (eta_t * cos_t));
+        ((r_s * r_s + r_p * r_p) / 2.0) as f32
+    }
+}
+
+pub fn cast_ray<R: Rng>(scene: Arc<Scene>, ray: &Ray, depth: u32, rng: &mut R) -> Color {
     if depth >= scene.max_recursion_depth {
         return BLACK;
     }
 
-    let intersection = scene.trace(&ray);
-    let scene = scene.clone();
-    intersection
-        .map(|i| get_color(scene, &ray, &i, depth))
-        .unwrap_or(BLACK)
+    let intersection = scene.trace(ray);
+    if let Some((distance, light)) = scene.trace_lights(ray) {
+        if intersection.as_ref().is_none_or(|i| distance < i.distance) {
+            return light.emission();
+        }
+    }
+
+    match intersection {
+        Some(i) => {
+            let hit_point = ray.origin + (ray.direction * i.distance);
+            let texture_coords = i.element.texture_coords_at(&hit_point, &i.detail, ray.time);
+            let emitted = i.element.material().emission(&texture_coords);
+            emitted + get_color(scene.clone(), ray, &i, depth, rng)
+        }
+        None => scene.background(&ray.direction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut roots: Vec<f64>) -> Vec<f64> {
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        roots
+    }
+
+    fn assert_roots(roots: Vec<f64>, expected: &[f64]) {
+        let roots = sorted(roots);
+        assert_eq!(roots.len(), expected.len(), "{:?}", roots);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-9, "{:?}", roots);
+        }
+    }
+
+    fn ray(origin: (f64, f64, f64), direction: (f64, f64, f64)) -> Ray {
+        Ray {
+            origin: Point {
+                x: origin.0,
+                y: origin.1,
+                z: origin.2,
+            },
+            direction: Vector3 {
+                x: direction.0,
+                y: direction.1,
+                z: direction.2,
+            },
+            time: 0.0,
+        }
+    }
+
+    fn torus() -> Torus {
+        serde_json::from_str(
+            r#"{"center": {"x": 0.0, "y": 0.0, "z": 0.0},
+                "major_radius": 2.0, "minor_radius": 0.5,
+                "material": {"coloration": {"Color": {"red": 1.0, "green": 1.0, "blue": 1.0}},
+                             "albedo": 0.5, "surface": "Diffuse"}}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn quartic_with_four_distinct_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(
+            solve_quartic(-10.0, 35.0, -50.0, 24.0),
+            &[1.0, 2.0, 3.0, 4.0],
+        );
+    }
+
+    #[test]
+    fn biquadratic_quartic() {
+        // (x² - 1)(x² - 4)
+        assert_roots(solve_quartic(0.0, -5.0, 0.0, 4.0), &[-2.0, -1.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn quartic_with_two_real_roots() {
+        // (x - 1)(x + 3)(x² + 1)
+        assert_roots(solve_quartic(2.0, -2.0, 2.0, -3.0), &[-3.0, 1.0]);
+    }
+
+    #[test]
+    fn quartic_without_real_roots() {
+        // (x² + 1)(x² + 4) and (x² + 2x + 2)(x² - 2x + 5)
+        assert_roots(solve_quartic(0.0, 5.0, 0.0, 4.0), &[]);
+        assert_roots(solve_quartic(0.0, 3.0, 6.0, 10.0), &[]);
+    }
+
+    #[test]
+    fn torus_is_hit_on_its_outer_side() {
+        let torus = torus();
+        let ray = ray((0.0, 0.0, 10.0), (0.0, 0.0, -1.0));
+        assert_roots(torus.crossings(&ray), &[7.5, 8.5, 11.5, 12.5]);
+        let distance = torus.intersect(&ray).unwrap();
+        assert!((distance - 7.5).abs() < 1e-9);
+        let normal = torus.surface_normal(
+            &(ray.origin + ray.direction * distance),
+            &HitDetail::default(),
+        );
+        assert!((normal.z - 1.0).abs() < 1e-9, "{:?}", normal);
+    }
+
+    #[test]
+    fn torus_is_hit_on_top_of_its_tube() {
+        let torus = torus();
+        let ray = ray((2.0, 10.0, 0.0), (0.0, -1.0, 0.0));
+        let distance = torus.intersect(&ray).unwrap();
+        assert!((distance - 9.5).abs() < 1e-9);
+        let normal = torus.surface_normal(
+            &(ray.origin + ray.direction * distance),
+            &HitDetail::default(),
+        );
+        assert!((normal.y - 1.0).abs() < 1e-9, "{:?}", normal);
+    }
+
+    #[test]
+    fn torus_is_missed_through_its_hole() {
+        let torus = torus();
+        assert_eq!(
+            torus.intersect(&ray((0.0, 10.0, 0.0), (0.0, -1.0, 0.0))),
+            None
+        );
+        assert_eq!(
+            torus.intersect(&ray((0.0, 5.0, 10.0), (0.0, 0.0, -1.0))),
+            None
+        );
+    }
+    #[test]
+    fn flat_box_is_hit_on_its_face() {
+        let panel: AxisAlignedBox = serde_json::from_str(
+            r#"{"min": {"x": 1.0, "y": -1.0, "z": -2.0}, "max": {"x": 1.0, "y": 1.0, "z": 2.0},
+                "material": {"coloration": {"Color": {"red": 1.0, "green": 1.0, "blue": 1.0}},
+                             "albedo": 0.5, "surface": "Diffuse"}}"#,
+        )
+        .unwrap();
+        for &(origin, direction) in &[(5.0, -1.0), (-5.0, 1.0)] {
+            let ray = ray((origin, 0.5, 1.0), (direction, 0.0, 0.0));
+            let distance = panel.intersect(&ray).unwrap();
+            let hit_point = ray.origin + ray.direction * distance;
+            let normal = panel.surface_normal(&hit_point, &HitDetail::default());
+            assert_eq!((normal.x.abs(), normal.y, normal.z), (1.0, 0.0, 0.0));
+            let coords = panel.texture_coords(&hit_point, &HitDetail::default());
+            assert_eq!((coords.x, coords.y), (0.75, 0.25));
+        }
+    }
 }
@@ -0,0 +1,284 @@
+use std::{
+    f64::consts::{FRAC_PI_2, FRAC_PI_4},
+    str::FromStr,
+};
+
+use rand::Rng;
+
+use crate::vector::Vector3;
+
+/// Reconstruction filter used to weight the samples taken around a pixel.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum Filter {
+    /// Equal weight over the pixel's own footprint.
+    #[default]
+    Box,
+    /// Linear falloff reaching zero one pixel away from the center.
+    Tent,
+    /// Truncated Gaussian (alpha = 2) with a radius of 1.5 pixels.
+    Gaussian,
+    /// Mitchell-Netravali cubic (B = C = 1/3) with a radius of 2 pixels.
+    Mitchell,
+}
+
+impl Filter {
+    /// Half-width of the filter's support, in pixels.
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    /// Filter value at distance `d` from the pixel center along one axis;
+    /// all filters are separable.
+    pub fn weight_1d(&self, d: f64) -> f64 {
+        let radius = self.radius();
+        let d = d.abs();
+        if d > radius {
+            return 0.0;
+        }
+
+        match *self {
+            Filter::Box => 1.0,
+            Filter::Tent => radius - d,
+            Filter::Gaussian => {
+                const ALPHA: f64 = 2.0;
+                ((-ALPHA * d * d).exp() - (-ALPHA * radius * radius).exp()).max(0.0)
+            }
+            Filter::Mitchell => mitchell(d),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            _ => Err(format!("Unknown filter: {}", s)),
+        }
+    }
+}
+
+/// Mitchell-Netravali cubic over [0, 2] with B = C = 1/3.
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1.0 / 3.0;
+    const C: f64 = 1.0 / 3.0;
+    let x = x.abs();
+    if x < 1.0 {
+        ((12.0 - 9.0 * B - 6.0 * C) * x * x * x
+            + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+            + (6.0 - 2.0 * B))
+            / 6.0
+    } else if x < 2.0 {
+        ((-B - 6.0 * C) * x * x * x
+            + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C))
+            / 6.0
+    } else {
+        0.0
+    }
+}
+
+/// Number of bins used to tabulate a filter for importance sampling.
+const FILTER_TABLE_SIZE: usize = 64;
+
+/// Draws pixel sample offsets distributed proportionally to the absolute
+/// value of a filter.
+///
+/// Each sample then only carries the sign of the filter as its weight, so
+/// filters with negative lobes (Mitchell) don't produce tiny weight sums when
+/// a negative sample lands next to a few barely positive ones.
+#[derive(Clone, Debug)]
+pub struct FilterSampler {
+    radius: f64,
+    cdf: Vec<f64>,
+    signs: Vec<f64>,
+}
+
+impl FilterSampler {
+    pub fn new(filter: Filter) -> Self {
+        let radius = filter.radius();
+        let bin_width = 2.0 * radius / FILTER_TABLE_SIZE as f64;
+        let values: Vec<f64> = (0..FILTER_TABLE_SIZE)
+            .map(|i| filter.weight_1d(-radius + (i as f64 + 0.5) * bin_width))
+            .collect();
+
+        let mut cdf = Vec::with_capacity(FILTER_TABLE_SIZE + 1);
+        cdf.push(0.0);
+        for v in &values {
+            let last = cdf[cdf.len() - 1];
+            cdf.push(last + v.abs());
+        }
+        let total = cdf[FILTER_TABLE_SIZE];
+        for c in cdf.iter_mut() {
+            *c /= total;
+        }
+
+        Self {
+            radius,
+            cdf,
+            signs: values.iter().map(|v| v.signum()).collect(),
+        }
+    }
+
+    fn sample_1d(&self, u: f64) -> (f64, f64) {
+        let bin = (self.cdf.partition_point(|&c| c <= u) - 1).min(FILTER_TABLE_SIZE - 1);
+        let (lo, hi) = (self.cdf[bin], self.cdf[bin + 1]);
+        let within = if hi > lo { (u - lo) / (hi - lo) } else { 0.5 };
+        let bin_width = 2.0 * self.radius / FILTER_TABLE_SIZE as f64;
+        (
+            -self.radius + (bin as f64 + within) * bin_width,
+            self.signs[bin],
+        )
+    }
+
+    /// Maps a point of the unit square to an offset from the pixel center
+    /// and the weight of the sample taken there.
+    pub fn sample(&self, u: f64, v: f64) -> (f64, f64, f64) {
+        let (dx, sign_x) = self.sample_1d(u);
+        let (dy, sign_y) = self.sample_1d(v);
+        (dx, dy, sign_x * sign_y)
+    }
+}
+
+/// Jittered points in the unit square.
+///
+/// The largest square number of samples not exceeding `count` is stratified
+/// over a grid; any remainder is placed uniformly at random.
+pub fn stratified_samples<R: Rng>(count: u32, rng: &mut R) -> Vec<(f64, f64)> {
+    let strata = (count as f64).sqrt() as u32;
+    let cell = 1.0 / strata as f64;
+    let mut samples = Vec::with_capacity(count as usize);
+
+    for sy in 0..strata {
+        for sx in 0..strata {
+            samples.push((
+                (sx as f64 + rng.gen::<f64>()) * cell,
+                (sy as f64 + rng.gen::<f64>()) * cell,
+            ));
+        }
+    }
+    for _ in strata * strata..count {
+        samples.push((rng.gen(), rng.gen()));
+    }
+    samples
+}
+
+/// Maps a point of the unit square to the unit disk, preserving stratification.
+pub fn concentric_disk(u: f64, v: f64) -> (f64, f64) {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, FRAC_PI_2 - FRAC_PI_4 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Two unit vectors that form an orthonormal basis together with `normal`.
+pub fn tangent_frame(normal: &Vector3) -> (Vector3, Vector3) {
+    let helper = if normal.x.abs() > 0.9 {
+        Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    } else {
+        Vector3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    };
+    let tangent = normal.cross(&helper).normalize();
+    let bitangent = normal.cross(&tangent);
+    (tangent, bitangent)
+}
+
+/// Direction in the hemisphere around `normal` with a pdf of cos(θ)/π.
+pub fn cosine_hemisphere<R: Rng>(normal: &Vector3, rng: &mut R) -> Vector3 {
+    let (x, y) = concentric_disk(rng.gen(), rng.gen());
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    let (tangent, bitangent) = tangent_frame(normal);
+    (tangent * x + bitangent * y + *normal * z).normalize()
+}
+
+/// Direction on the unit sphere with a uniform pdf of 1/(4π).
+pub fn uniform_sphere(u: f64, v: f64) -> Vector3 {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * v;
+    Vector3 {
+        x: r * phi.cos(),
+        y: r * phi.sin(),
+        z,
+    }
+}
+
+/// Piecewise-constant distribution over `n` equally sized bins.
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    cdf: Vec<f64>,
+    values: Vec<f64>,
+    total: f64,
+}
+
+impl Distribution1D {
+    /// Negative values are treated as zero. If every value is zero, all bins
+    /// become equally likely.
+    pub fn new(values: &[f64]) -> Self {
+        let values: Vec<f64> = values.iter().map(|v| v.max(0.0)).collect();
+        let mut cdf = Vec::with_capacity(values.len() + 1);
+        cdf.push(0.0);
+        for v in &values {
+            let last = cdf[cdf.len() - 1];
+            cdf.push(last + v);
+        }
+        let total = cdf[values.len()];
+        let count = values.len() as f64;
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if total > 0.0 {
+                *c / total
+            } else {
+                i as f64 / count
+            };
+        }
+        Self { cdf, values, total }
+    }
+
+    /// Sum of the bin values.
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    /// Picks a bin for `u` in [0, 1) and returns it with the position of `u`
+    /// within it, also in [0, 1).
+    pub fn sample(&self, u: f64) -> (usize, f64) {
+        let last = self.values.len() - 1;
+        let bin = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(last);
+        let (lo, hi) = (self.cdf[bin], self.cdf[bin + 1]);
+        let within = if hi > lo { (u - lo) / (hi - lo) } else { 0.5 };
+        (bin, within.clamp(0.0, 1.0 - f64::EPSILON))
+    }
+
+    /// Probability of `sample` picking `bin`.
+    pub fn probability(&self, bin: usize) -> f64 {
+        if self.total > 0.0 {
+            self.values[bin] / self.total
+        } else {
+            1.0 / self.values.len() as f64
+        }
+    }
+}
@@ -1,9 +1,38 @@
+use std::{collections::HashMap, sync::Arc};
+
 use crate::{
+    animation::Track,
+    bvh::{BoundingBox, Bvh},
+    camera::Camera,
+    color::{Color, BLACK},
     element::{Element, Intersection},
+    environment::Environment,
     light::Light,
     rendering::{Intersectable, Ray},
+    sampling::Filter,
+    tonemap::ToneMapping,
+    vector::Vector3,
 };
 
+/// How `Scene::trace` finds the closest element along a ray.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum Acceleration {
+    /// Bounding volume hierarchy built over the element bounds.
+    #[default]
+    Bvh,
+    /// Test every element against every ray; useful to compare results.
+    Linear,
+}
+/// Algorithm used to compute the light arriving along camera rays.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum Integrator {
+    /// Recursive ray tracing with direct lighting only.
+    #[default]
+    Whitted,
+    /// Monte Carlo path tracing with indirect (global) illumination.
+    PathTracer,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Scene {
     pub width: u32,
@@ -13,13 +42,187 @@ pub struct Scene {
     pub lights: Vec<Light>,
     pub shadow_bias: f64,
     pub max_recursion_depth: u32,
+    #[serde(default)]
+    pub acceleration: Acceleration,
+    #[serde(default)]
+    pub camera: Camera,
+    #[serde(default = "default_samples_per_pixel")]
+    pub samples_per_pixel: u32,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub integrator: Integrator,
+    /// Applied when converting to 8-bit output; HDR output stays linear.
+    #[serde(default)]
+    pub tone_mapping: ToneMapping,
+    /// Seen by rays that leave the scene; black when missing.
+    #[serde(default)]
+    pub environment: Option<Environment>,
+    /// Named elements that `Element::Instance` entries refer to. They aren't
+    /// rendered on their own.
+    #[serde(default)]
+    pub geometries: HashMap<String, Arc<Element>>,
+    /// Keyframed properties, applied by `at_frame`.
+    #[serde(default)]
+    pub animation: Vec<Track>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub bvh: Option<Bvh>,
+    /// Indices of the emissive elements that can be sampled as lights.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub emitters: Vec<usize>,
+}
+
+fn default_samples_per_pixel() -> u32 {
+    1
 }
 
 impl Scene {
-    pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
+    /// The scene as it is at `frame` of its animation. The shutter interval
+    /// moves along with the frame, so element motions carry on from one
+    /// frame to the next with times measured in frames.
+    pub fn at_frame(&self, frame: f64) -> Result<Scene, String> {
+        let mut scene = self.clone();
+        for track in &self.animation {
+            track.apply(&mut scene, frame)?;
+        }
+        scene.camera.shutter_open += frame;
+        scene.camera.shutter_close += frame;
+        // Elements may have moved.
+        scene.bvh = None;
+        Ok(scene)
+    }
+
+    /// Builds the element hierarchy used by `trace`. Has to be called again
+    /// whenever `elements` or the camera's shutter interval change.
+    ///
+    /// Expects `resolve_instances` to have run, since the bounds of an
+    /// instance are those of its shared geometry.
+    pub fn build_bvh(&mut self) {
+        let (open, close) = (self.camera.shutter_open, self.camera.shutter_close);
+        let bounds: Vec<BoundingBox> = self
+            .elements
+            .iter()
+            .map(|e| e.swept_bounds(open, close.max(open)))
+            .collect();
+        self.bvh = Some(Bvh::build(&bounds));
+    }
+
+    /// Points every instance at its shared geometry. Has to be called before
+    /// rendering whenever `elements` or `geometries` change.
+    ///
+    /// Also checks that only elements of the scene itself move: parts of a
+    /// CSG element or a shared geometry move with it instead.
+    pub fn resolve_instances(&mut self) -> Result<(), String> {
+        for (name, geometry) in &self.geometries {
+            if contains_instance(geometry) {
+                return Err(format!("Geometry {:?} can't contain instances", name));
+            }
+            if contains_motion(geometry) {
+                return Err(format!(
+                    "Geometry {:?} can't move; give its instances a motion instead",
+                    name
+                ));
+            }
+        }
+        for element in &self.elements {
+            if let Element::Csg(ref csg) = *element {
+                if contains_motion(&csg.left) || contains_motion(&csg.right) {
+                    return Err(
+                        "Parts of a CSG element can't move; give the element a motion instead"
+                            .to_string(),
+                    );
+                }
+            }
+        }
+        for element in self.elements.iter_mut() {
+            resolve_instance(element, &self.geometries)?;
+        }
+        Ok(())
+    }
+
+    /// Finds the elements next-event estimation treats as lights. Has to be
+    /// called again whenever `elements` changes.
+    pub fn collect_emitters(&mut self) {
+        self.emitters = self
+            .elements
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| {
+                e.material().emission.is_some() && e.surface_area().is_some_and(|a| a > 0.0)
+            })
+            .map(|(i, _)| i)
+            .collect();
+    }
+
+    pub fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        match (self.acceleration, &self.bvh) {
+            (Acceleration::Bvh, Some(bvh)) => bvh
+                .nearest(ray, |i| self.elements[i].hit(ray))
+                .map(|(i, d, detail)| Intersection::new(d, &self.elements[i], detail)),
+            _ => self.trace_linear(ray),
+        }
+    }
+
+    /// Light arriving along a ray in `direction` that hit nothing.
+    pub fn background(&self, direction: &Vector3) -> Color {
+        self.environment
+            .as_ref()
+            .map_or(BLACK, |e| e.radiance(direction))
+    }
+
+    /// Closest area light hit by `ray`; lights aren't part of `elements`, so
+    /// they neither cast shadows nor show up in `trace`.
+    pub fn trace_lights(&self, ray: &Ray) -> Option<(f64, &Light)> {
+        self.lights
+            .iter()
+            .filter_map(|l| l.intersect(ray).map(|d| (d, l)))
+            .min_by(|l1, l2| l1.0.partial_cmp(&l2.0).unwrap())
+    }
+
+    pub(crate) fn trace_linear(&self, ray: &Ray) -> Option<Intersection<'_>> {
         self.elements
             .iter()
-            .filter_map(|e| e.intersect(ray).map(|d| Intersection::new(d, e)))
+            .filter_map(|e| {
+                e.hit(ray)
+                    .map(|(d, detail)| Intersection::new(d, e, detail))
+            })
             .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
     }
 }
+
+fn resolve_instance(
+    element: &mut Element,
+    geometries: &HashMap<String, Arc<Element>>,
+) -> Result<(), String> {
+    match *element {
+        Element::Instance(ref mut instance) => {
+            let geometry = geometries
+                .get(&instance.geometry)
+                .ok_or_else(|| format!("Unknown geometry {:?}", instance.geometry))?;
+            instance.resolved = Some(geometry.clone());
+        }
+        Element::Csg(ref mut csg) => {
+            resolve_instance(&mut csg.left, geometries)?;
+            resolve_instance(&mut csg.right, geometries)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn contains_instance(element: &Element) -> bool {
+    match *element {
+        Element::Instance(_) => true,
+        Element::Csg(ref csg) => contains_instance(&csg.left) || contains_instance(&csg.right),
+        _ => false,
+    }
+}
+
+fn contains_motion(element: &Element) -> bool {
+    element.motion().is_some()
+        || match *element {
+            Element::Csg(ref csg) => contains_motion(&csg.left) || contains_motion(&csg.right),
+            _ => false,
+        }
+}
@@ -0,0 +1,333 @@
+use crate::{
+    bvh::BoundingBox,
+    material::Material,
+    motion::Motion,
+    point::Point,
+    rendering::{slabs, Ray},
+    vector::{Transform, Vector3},
+};
+
+/// Iterations of the bisection that pins down where a ray crosses the
+/// surface once marching has stepped over it.
+const REFINE_STEPS: u32 = 10;
+
+/// Shape given by a signed distance function, rendered by sphere tracing.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Sdf {
+    pub shape: SdfNode,
+    pub material: Material,
+    /// Most steps taken along a single ray.
+    #[serde(default = "default_max_steps")]
+    pub max_steps: u32,
+    /// Distance below which a point counts as being on the surface.
+    #[serde(default = "default_epsilon")]
+    pub epsilon: f64,
+    /// Fraction of the distance bound advanced per step. Shapes whose
+    /// distance is only an estimate, like twists, need less than 1.
+    #[serde(default = "default_step_scale")]
+    pub step_scale: f64,
+    /// How far rays are followed through shapes without bounds, such as
+    /// repetitions.
+    #[serde(default = "default_max_distance")]
+    pub max_distance: f64,
+    #[serde(default)]
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub motion: Option<Motion>,
+}
+
+fn default_max_steps() -> u32 {
+    256
+}
+
+fn default_epsilon() -> f64 {
+    1e-4
+}
+
+fn default_step_scale() -> f64 {
+    1.0
+}
+
+fn default_max_distance() -> f64 {
+    100.0
+}
+
+fn default_power() -> f64 {
+    8.0
+}
+
+fn default_iterations() -> u32 {
+    10
+}
+
+/// Distance expression, centered on the origin unless translated.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum SdfNode {
+    Sphere {
+        radius: f64,
+    },
+    Box {
+        /// Half the size of the box along each axis.
+        half_extents: Vector3,
+    },
+    /// Ring around the Y axis.
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    /// Union that blends the two shapes within `smoothness` of each other.
+    SmoothUnion {
+        left: Box<SdfNode>,
+        right: Box<SdfNode>,
+        #[serde(default)]
+        smoothness: f64,
+    },
+    Translate {
+        offset: Vector3,
+        shape: Box<SdfNode>,
+    },
+    /// Infinite copies of `shape`, `period` apart along each axis; axes with
+    /// a period of 0 aren't repeated.
+    Repeat {
+        period: Vector3,
+        shape: Box<SdfNode>,
+    },
+    /// Rotates `shape` around the Y axis by `angle` degrees per unit of
+    /// height.
+    Twist {
+        angle: f64,
+        shape: Box<SdfNode>,
+    },
+    Mandelbulb {
+        #[serde(default = "default_power")]
+        power: f64,
+        #[serde(default = "default_iterations")]
+        iterations: u32,
+    },
+}
+
+impl SdfNode {
+    /// Signed distance from `p` to the surface, negative inside. Only a
+    /// lower bound for blends, twists and fractals.
+    pub fn distance(&self, p: &Vector3) -> f64 {
+        match *self {
+            SdfNode::Sphere { radius } => p.length() - radius,
+            SdfNode::Box { ref half_extents } => {
+                let q = Vector3 {
+                    x: p.x.abs() - half_extents.x,
+                    y: p.y.abs() - half_extents.y,
+                    z: p.z.abs() - half_extents.z,
+                };
+                let outside = Vector3 {
+                    x: q.x.max(0.0),
+                    y: q.y.max(0.0),
+                    z: q.z.max(0.0),
+                };
+                outside.length() + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            SdfNode::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let radial = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (radial * radial + p.y * p.y).sqrt() - minor_radius
+            }
+            SdfNode::SmoothUnion {
+                ref left,
+                ref right,
+                smoothness,
+            } => {
+                let (a, b) = (left.distance(p), right.distance(p));
+                if smoothness <= 0.0 {
+                    return a.min(b);
+                }
+                // Polynomial smooth minimum.
+                let h = (0.5 + 0.5 * (b - a) / smoothness).clamp(0.0, 1.0);
+                b + (a - b) * h - smoothness * h * (1.0 - h)
+            }
+            SdfNode::Translate {
+                ref offset,
+                ref shape,
+            } => shape.distance(&(*p - *offset)),
+            SdfNode::Repeat {
+                ref period,
+                ref shape,
+            } => {
+                let wrap = |v: f64, period: f64| {
+                    if period > 0.0 {
+                        v - period * (v / period).round()
+                    } else {
+                        v
+                    }
+                };
+                shape.distance(&Vector3 {
+                    x: wrap(p.x, period.x),
+                    y: wrap(p.y, period.y),
+                    z: wrap(p.z, period.z),
+                })
+            }
+            SdfNode::Twist { angle, ref shape } => {
+                let (sin, cos) = (-angle.to_radians() * p.y).sin_cos();
+                shape.distance(&Vector3 {
+                    x: cos * p.x - sin * p.z,
+                    y: p.y,
+                    z: sin * p.x + cos * p.z,
+                })
+            }
+            SdfNode::Mandelbulb { power, iterations } => mandelbulb(p, power, iterations),
+        }
+    }
+
+    /// Box the surface lies within; infinite for repetitions.
+    pub fn bounds(&self) -> BoundingBox {
+        let centered = |half: Vector3| BoundingBox {
+            min: Point::zero() + -half,
+            max: Point::zero() + half,
+        };
+        match *self {
+            SdfNode::Sphere { radius } => centered(Vector3::from_one(radius)),
+            SdfNode::Box { half_extents } => centered(half_extents),
+            SdfNode::Torus {
+                major_radius,
+                minor_radius,
+            } => centered(Vector3 {
+                x: major_radius + minor_radius,
+                y: minor_radius,
+                z: major_radius + minor_radius,
+            }),
+            SdfNode::SmoothUnion {
+                ref left,
+                ref right,
+                smoothness,
+            } => {
+                // Blending can only grow the shapes by a quarter of the
+                // smoothness.
+                let bounds = left.bounds().union(&right.bounds());
+                let margin = Vector3::from_one(smoothness.max(0.0) / 4.0);
+                BoundingBox {
+                    min: bounds.min + -margin,
+                    max: bounds.max + margin,
+                }
+            }
+            SdfNode::Translate {
+                ref offset,
+                ref shape,
+            } => {
+                let bounds = shape.bounds();
+                BoundingBox {
+                    min: bounds.min + *offset,
+                    max: bounds.max + *offset,
+                }
+            }
+            SdfNode::Repeat { .. } => BoundingBox::infinite(),
+            SdfNode::Twist { ref shape, .. } => {
+                let bounds = shape.bounds();
+                if !bounds.is_finite() {
+                    return bounds;
+                }
+                let x = bounds.min.x.abs().max(bounds.max.x.abs());
+                let z = bounds.min.z.abs().max(bounds.max.z.abs());
+                let radius = (x * x + z * z).sqrt();
+                BoundingBox {
+                    min: Point {
+                        x: -radius,
+                        y: bounds.min.y,
+                        z: -radius,
+                    },
+                    max: Point {
+                        x: radius,
+                        y: bounds.max.y,
+                        z: radius,
+                    },
+                }
+            }
+            // Points further out than the bailout radius escape.
+            SdfNode::Mandelbulb { .. } => centered(Vector3::from_one(2.0)),
+        }
+    }
+}
+
+/// Distance estimate for the Mandelbulb fractal, from the growth of the
+/// derivative of its iteration.
+fn mandelbulb(p: &Vector3, power: f64, iterations: u32) -> f64 {
+    let mut z = *p;
+    let mut dr = 1.0;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        if r > 2.0 || r == 0.0 {
+            break;
+        }
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        let zr = r.powf(power);
+        z = Vector3 {
+            x: theta.sin() * phi.cos(),
+            y: theta.sin() * phi.sin(),
+            z: theta.cos(),
+        } * zr
+            + *p;
+        r = z.length();
+    }
+    if r == 0.0 {
+        return 0.0;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+impl Sdf {
+    pub fn distance(&self, p: &Point) -> f64 {
+        self.shape.distance(&(*p - Point::zero()))
+    }
+
+    /// Distances between `start` and `end` at which `ray` crosses the
+    /// surface, stopping after the first one if `first_only` is set.
+    pub fn march(&self, ray: &Ray, start: f64, end: f64, first_only: bool) -> Vec<f64> {
+        let at = |t: f64| self.distance(&(ray.origin + ray.direction * t)) - self.epsilon;
+        let mut crossings = Vec::new();
+        let mut t = start;
+        let mut d = at(t);
+        let mut inside = d < 0.0;
+        for _ in 0..self.max_steps {
+            let previous = t;
+            t += (d.abs() * self.step_scale).max(self.epsilon);
+            if t > end {
+                break;
+            }
+            d = at(t);
+            if (d < 0.0) != inside {
+                crossings.push(refine(&at, previous, t));
+                inside = !inside;
+                if first_only {
+                    break;
+                }
+            }
+        }
+        crossings
+    }
+
+    /// Distances along `ray` between which to march: where the line passes
+    /// through the bounds, or within `max_distance` of the origin for
+    /// unbounded shapes.
+    pub fn range(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let bounds = self.shape.bounds();
+        if !bounds.is_finite() {
+            return Some((-self.max_distance, self.max_distance));
+        }
+        slabs(&bounds.min, &bounds.max, ray)
+    }
+}
+
+/// Bisects between `a` and `b`, where `f` changes sign.
+fn refine<F: Fn(f64) -> f64>(f: &F, mut a: f64, mut b: f64) -> f64 {
+    let sign_a = f(a) < 0.0;
+    for _ in 0..REFINE_STEPS {
+        let middle = 0.5 * (a + b);
+        if (f(middle) < 0.0) == sign_a {
+            a = middle;
+        } else {
+            b = middle;
+        }
+    }
+    0.5 * (a + b)
+}
@@ -0,0 +1,102 @@
+use std::str::FromStr;
+
+use crate::color::Color;
+
+/// Curve used to compress linear radiance into displayable [0, 1] values.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub enum ToneMapOperator {
+    /// Hard clip at 1.0.
+    #[default]
+    Clamp,
+    /// `c / (1 + c)` per channel.
+    Reinhard,
+    /// Reinhard on luminance, reaching white at `ToneMapping::white_point`.
+    ExtendedReinhard,
+    /// Narkowicz's fit of the ACES filmic reference curve.
+    Aces,
+    /// John Hable's Uncharted 2 filmic curve.
+    Hable,
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "clamp" => Ok(ToneMapOperator::Clamp),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "extended-reinhard" => Ok(ToneMapOperator::ExtendedReinhard),
+            "aces" => Ok(ToneMapOperator::Aces),
+            "hable" => Ok(ToneMapOperator::Hable),
+            _ => Err(format!("Unknown tone mapping operator: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Exposure adjustment in stops, applied before the operator.
+    pub exposure: f32,
+    /// Smallest luminance mapped to white by `ExtendedReinhard`.
+    pub white_point: f32,
+}
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::Clamp,
+            exposure: 0.0,
+            white_point: 4.0,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Maps a linear color into [0, 1], ready for gamma encoding.
+    pub fn apply(&self, color: Color) -> Color {
+        let color = color * 2f32.powf(self.exposure);
+        let mapped = match self.operator {
+            ToneMapOperator::Clamp => color,
+            ToneMapOperator::Reinhard => per_channel(color, |c| c / (1.0 + c)),
+            ToneMapOperator::ExtendedReinhard => {
+                let luminance = color.luminance();
+                if luminance <= 0.0 {
+                    color
+                } else {
+                    let white2 = self.white_point * self.white_point;
+                    let mapped = luminance * (1.0 + luminance / white2) / (1.0 + luminance);
+                    color * (mapped / luminance)
+                }
+            }
+            ToneMapOperator::Aces => per_channel(color, |c| {
+                (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)
+            }),
+            ToneMapOperator::Hable => {
+                const EXPOSURE_BIAS: f32 = 2.0;
+                const WHITE: f32 = 11.2;
+                let white_scale = hable_partial(WHITE).recip();
+                per_channel(color, |c| hable_partial(c * EXPOSURE_BIAS) * white_scale)
+            }
+        };
+        mapped.clamp()
+    }
+}
+
+fn per_channel<F: Fn(f32) -> f32>(color: Color, f: F) -> Color {
+    Color {
+        red: f(color.red.max(0.0)),
+        green: f(color.green.max(0.0)),
+        blue: f(color.blue.max(0.0)),
+    }
+}
+
+fn hable_partial(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
@@ -1,7 +1,12 @@
-use std::ops::{Mul, Neg, Sub};
+use std::{
+    convert::TryFrom,
+    ops::{Add, Mul, Neg, Sub},
+};
 
 use serde::{Deserialize, Deserializer};
 
+use crate::point::Point;
+
 #[derive(Copy, Clone, Debug, Deserialize, Serialize)]
 #[repr(C)]
 pub struct Vector3 {
@@ -56,6 +61,18 @@ impl Vector3 {
     }
 }
 
+impl Add for Vector3 {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+}
+
 impl Sub for Vector3 {
     type Output = Self;
 
@@ -111,3 +128,436 @@ impl Neg for Vector3 {
         }
     }
 }
+
+/// Row-major 4×4 matrix acting on column vectors.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+impl Matrix4 {
+    pub fn identity() -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Self { m }
+    }
+
+    pub fn translation(offset: &Vector3) -> Self {
+        let mut t = Self::identity();
+        t.m[0][3] = offset.x;
+        t.m[1][3] = offset.y;
+        t.m[2][3] = offset.z;
+        t
+    }
+
+    pub fn scaling(factors: &Vector3) -> Self {
+        let mut s = Self::identity();
+        s.m[0][0] = factors.x;
+        s.m[1][1] = factors.y;
+        s.m[2][2] = factors.z;
+        s
+    }
+
+    /// Counter-clockwise rotation by `degrees` around `axis`, looking down
+    /// the axis towards the origin.
+    pub fn rotation(axis: &Vector3, degrees: f64) -> Self {
+        let a = axis.normalize();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        let mut r = Self::identity();
+        r.m[0] = [
+            t * a.x * a.x + cos,
+            t * a.x * a.y - sin * a.z,
+            t * a.x * a.z + sin * a.y,
+            0.0,
+        ];
+        r.m[1] = [
+            t * a.x * a.y + sin * a.z,
+            t * a.y * a.y + cos,
+            t * a.y * a.z - sin * a.x,
+            0.0,
+        ];
+        r.m[2] = [
+            t * a.x * a.z - sin * a.y,
+            t * a.y * a.z + sin * a.x,
+            t * a.z * a.z + cos,
+            0.0,
+        ];
+        r
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut t = [[0.0; 4]; 4];
+        for (i, row) in t.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Self { m: t }
+    }
+
+    /// Inverse by Gauss-Jordan elimination, or `None` if the matrix is
+    /// singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = a[col][col].recip();
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self { m: inv })
+    }
+
+    /// Determinant of the upper-left 3×3 part, i.e. how the matrix scales
+    /// volumes.
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: &Point) -> Point {
+        let m = &self.m;
+        Point {
+            x: m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            y: m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            z: m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        }
+    }
+
+    /// Transforms a direction, ignoring the translation.
+    pub fn transform_vector(&self, v: &Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Self { m }
+    }
+}
+
+/// One step of a `Transform`, as written in scene files.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TransformStep {
+    Translate(Vector3),
+    /// Rotation by `angle` degrees around `axis`.
+    Rotate {
+        axis: Vector3,
+        angle: f64,
+    },
+    Scale(Vector3),
+    /// Raw row-major matrix; the last row should be `[0, 0, 0, 1]`.
+    Matrix([[f64; 4]; 4]),
+}
+impl TransformStep {
+    fn matrix(&self) -> Matrix4 {
+        match *self {
+            TransformStep::Translate(ref offset) => Matrix4::translation(offset),
+            TransformStep::Rotate { ref axis, angle } => Matrix4::rotation(axis, angle),
+            TransformStep::Scale(ref factors) => Matrix4::scaling(factors),
+            TransformStep::Matrix(m) => Matrix4 { m },
+        }
+    }
+
+    /// The step's matrix and its inverse, worked out directly for all but
+    /// raw matrices; `None` if the step is singular.
+    fn affine(&self) -> Option<Affine> {
+        let inverse = match *self {
+            TransformStep::Translate(ref offset) => Matrix4::translation(&-*offset),
+            TransformStep::Rotate { ref axis, angle } => Matrix4::rotation(axis, -angle),
+            TransformStep::Scale(ref factors) => {
+                if factors.x == 0.0 || factors.y == 0.0 || factors.z == 0.0 {
+                    return None;
+                }
+                Matrix4::scaling(&Vector3 {
+                    x: factors.x.recip(),
+                    y: factors.y.recip(),
+                    z: factors.z.recip(),
+                })
+            }
+            TransformStep::Matrix(m) => return Affine::new(Matrix4 { m }),
+        };
+        Some(Affine {
+            matrix: self.matrix(),
+            inverse,
+        })
+    }
+
+    /// Step `t` of the way from `self` to `other`, or `None` if they are
+    /// different kinds of step.
+    fn lerp(&self, other: &TransformStep, t: f64) -> Option<TransformStep> {
+        let mix = |a: f64, b: f64| a + (b - a) * t;
+        let mix_vector = |a: &Vector3, b: &Vector3| *a + (*b - *a) * t;
+        Some(match (self, other) {
+            (TransformStep::Translate(a), TransformStep::Translate(b)) => {
+                TransformStep::Translate(mix_vector(a, b))
+            }
+            (
+                TransformStep::Rotate { axis, angle },
+                TransformStep::Rotate {
+                    axis: other_axis,
+                    angle: other_angle,
+                },
+            ) => {
+                // Turning the other way around the opposite axis is the same
+                // rotation; using it keeps the mixed axis from passing
+                // through zero.
+                let (axis, other_axis) = (axis.normalize(), other_axis.normalize());
+                let (other_axis, other_angle) = if axis.dot(&other_axis) < 0.0 {
+                    (-other_axis, -*other_angle)
+                } else {
+                    (other_axis, *other_angle)
+                };
+                TransformStep::Rotate {
+                    axis: mix_vector(&axis, &other_axis),
+                    angle: mix(*angle, other_angle),
+                }
+            }
+            (TransformStep::Scale(a), TransformStep::Scale(b)) => {
+                TransformStep::Scale(mix_vector(a, b))
+            }
+            (TransformStep::Matrix(a), TransformStep::Matrix(b)) => {
+                let mut m = *a;
+                for (row, other_row) in m.iter_mut().zip(b.iter()) {
+                    for (value, other_value) in row.iter_mut().zip(other_row.iter()) {
+                        *value = mix(*value, *other_value);
+                    }
+                }
+                TransformStep::Matrix(m)
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// Matrices of an affine transformation and of its inverse.
+#[derive(Clone, Copy, Debug)]
+pub struct Affine {
+    pub matrix: Matrix4,
+    pub inverse: Matrix4,
+}
+impl Affine {
+    pub fn identity() -> Self {
+        Self {
+            matrix: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+        }
+    }
+
+    /// Inverts `matrix`; `None` if it is singular.
+    pub fn new(matrix: Matrix4) -> Option<Self> {
+        Some(Self {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    pub fn point_to_object(&self, p: &Point) -> Point {
+        self.inverse.transform_point(p)
+    }
+
+    pub fn point_to_world(&self, p: &Point) -> Point {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector_to_object(&self, v: &Vector3) -> Vector3 {
+        self.inverse.transform_vector(v)
+    }
+
+    /// Normals transform by the inverse transpose to stay perpendicular to
+    /// the surface. The result isn't normalized.
+    pub fn normal_to_world(&self, n: &Vector3) -> Vector3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+
+    /// Factor by which the transform scales small areas of a surface with
+    /// the object-space normal `n`.
+    pub fn area_scale(&self, n: &Vector3) -> f64 {
+        self.matrix.determinant3().abs() * self.normal_to_world(n).length()
+    }
+
+    /// Applies `self`, then `next`.
+    pub fn then(&self, next: &Affine) -> Affine {
+        Self {
+            matrix: next.matrix * self.matrix,
+            inverse: self.inverse * next.inverse,
+        }
+    }
+
+    /// Applies `self`, then moves by `offset`. Unlike `then`, this only
+    /// adjusts the translations.
+    pub fn translated(&self, offset: &Vector3) -> Affine {
+        let back = self.inverse.transform_vector(offset);
+        let (mut matrix, mut inverse) = (self.matrix, self.inverse);
+        matrix.m[0][3] += offset.x;
+        matrix.m[1][3] += offset.y;
+        matrix.m[2][3] += offset.z;
+        inverse.m[0][3] -= back.x;
+        inverse.m[1][3] -= back.y;
+        inverse.m[2][3] -= back.z;
+        Self { matrix, inverse }
+    }
+}
+
+/// Affine object-to-world transformation, deserialized from a list of steps
+/// applied to the object in order.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "Vec<TransformStep>", into = "Vec<TransformStep>")]
+pub struct Transform {
+    steps: Vec<TransformStep>,
+    pub affine: Affine,
+}
+impl Transform {
+    pub fn new(matrix: Matrix4) -> Option<Self> {
+        Some(Self {
+            steps: vec![TransformStep::Matrix(matrix.m)],
+            affine: Affine::new(matrix)?,
+        })
+    }
+
+    /// Moves by `offset`.
+    pub fn translation(offset: &Vector3) -> Self {
+        Self {
+            steps: vec![TransformStep::Translate(*offset)],
+            affine: Affine::identity().translated(offset),
+        }
+    }
+
+    /// `self`, then a move by `offset`.
+    pub fn translated(&self, offset: &Vector3) -> Self {
+        let mut steps = self.steps.clone();
+        steps.push(TransformStep::Translate(*offset));
+        Self {
+            steps,
+            affine: self.affine.translated(offset),
+        }
+    }
+
+    /// Transform `t` of the way from `self` to `other`. Steps are
+    /// interpolated one by one when both have the same kinds of step in
+    /// the same order, so rotations turn through the angles in between;
+    /// otherwise the matrices are.
+    pub fn lerp(&self, other: &Transform, t: f64) -> Affine {
+        let stepwise = if self.steps.len() == other.steps.len() {
+            self.steps
+                .iter()
+                .zip(other.steps.iter())
+                .try_fold(Affine::identity(), |affine, (a, b)| {
+                    Some(affine.then(&a.lerp(b, t)?.affine()?))
+                })
+        } else {
+            None
+        };
+        stepwise.unwrap_or_else(|| {
+            let (a, b) = (
+                TransformStep::Matrix(self.affine.matrix.m),
+                other.affine.matrix.m,
+            );
+            let mixed = a.lerp(&TransformStep::Matrix(b), t).unwrap();
+            // Matrices can pass through singular ones, like a scale
+            // crossing zero.
+            mixed.affine().unwrap_or(self.affine)
+        })
+    }
+}
+impl TryFrom<Vec<TransformStep>> for Transform {
+    type Error = String;
+
+    fn try_from(steps: Vec<TransformStep>) -> Result<Self, Self::Error> {
+        let matrix = steps
+            .iter()
+            .fold(Matrix4::identity(), |m, step| step.matrix() * m);
+        let affine =
+            Affine::new(matrix).ok_or_else(|| "Transform is not invertible".to_string())?;
+        Ok(Self { steps, affine })
+    }
+}
+impl From<Transform> for Vec<TransformStep> {
+    fn from(transform: Transform) -> Self {
+        transform.steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotation(axis: Vector3, angle: f64) -> Transform {
+        Transform::try_from(vec![TransformStep::Rotate { axis, angle }]).unwrap()
+    }
+
+    fn assert_close(a: &Matrix4, b: &Matrix4) {
+        for (row, other_row) in a.m.iter().zip(b.m.iter()) {
+            for (value, other_value) in row.iter().zip(other_row.iter()) {
+                assert!((value - other_value).abs() < 1e-9, "{:?} vs {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn rotations_around_opposite_axes_interpolate() {
+        let up = Vector3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        let (start, end) = (rotation(up, 90.0), rotation(-up, 90.0));
+        assert_close(&start.lerp(&end, 0.5).matrix, &Matrix4::identity());
+        assert_close(
+            &start.lerp(&end, 0.25).matrix,
+            &Matrix4::rotation(&up, 45.0),
+        );
+        assert_close(&start.lerp(&end, 1.0).matrix, &end.affine.matrix);
+    }
+
+    #[test]
+    fn rotations_turn_through_the_angles_in_between() {
+        let up = Vector3 {
+            x: 0.0,
+            y: 2.0,
+            z: 0.0,
+        };
+        let (start, end) = (rotation(up, 0.0), rotation(up, 720.0));
+        assert_close(
+            &start.lerp(&end, 0.125).matrix,
+            &Matrix4::rotation(&up, 90.0),
+        );
+    }
+}